@@ -0,0 +1,112 @@
+//! Admin HTTP endpoint for operating a running proxy.
+use actix_web::dev::Server;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+use crate::approval::ApprovalDecision;
+use crate::types::AdminConfig;
+use crate::McpProxy;
+
+#[derive(Clone)]
+struct AdminState {
+    proxy: McpProxy,
+    token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RejectBody {
+    reason: Option<String>,
+}
+
+/// Bind the admin API, the returned server runs when awaited or spawned
+pub fn admin_server(config: AdminConfig, proxy: McpProxy) -> Result<Server> {
+    info!(
+        "Starting admin server on http://{}:{}",
+        config.host, config.port
+    );
+    let state = AdminState {
+        proxy,
+        token: config.token.clone(),
+    };
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(state.clone()))
+            .route("/status", web::get().to(status))
+            .route("/approvals", web::get().to(list_approvals))
+            .route("/approvals/{id}/approve", web::post().to(approve))
+            .route("/approvals/{id}/reject", web::post().to(reject))
+    })
+    .bind((config.host.as_str(), config.port))?
+    .run();
+    Ok(server)
+}
+
+fn authorized(req: &HttpRequest, state: &AdminState) -> bool {
+    let Some(token) = &state.token else {
+        return true;
+    };
+    req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|provided| bool::from(provided.as_bytes().ct_eq(token.as_bytes())))
+}
+
+async fn status(req: HttpRequest, state: web::Data<AdminState>) -> HttpResponse {
+    if !authorized(&req, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(json!({
+        "breakers": state.proxy.breaker_status(),
+        "concurrency": state.proxy.limiter_status(),
+        "groups": state.proxy.group_status(),
+        "instances": state.proxy.instance_counts().await,
+        "pools": state.proxy.pool_status().await,
+        "restarts": state.proxy.restart_status(),
+        "cache": state.proxy.cache_stats().await,
+    }))
+}
+
+async fn list_approvals(req: HttpRequest, state: web::Data<AdminState>) -> HttpResponse {
+    if !authorized(&req, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(state.proxy.approvals().pending().await)
+}
+
+async fn approve(
+    req: HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<AdminState>,
+) -> HttpResponse {
+    if !authorized(&req, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    decide(&state, &path, ApprovalDecision::Approved).await
+}
+
+async fn reject(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: Option<web::Json<RejectBody>>,
+    state: web::Data<AdminState>,
+) -> HttpResponse {
+    if !authorized(&req, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let reason = body.and_then(|b| b.into_inner().reason);
+    decide(&state, &path, ApprovalDecision::Rejected(reason)).await
+}
+
+async fn decide(state: &AdminState, id: &str, decision: ApprovalDecision) -> HttpResponse {
+    if state.proxy.approvals().decide(id, decision).await {
+        HttpResponse::Ok().json(json!({ "id": id, "status": "decided" }))
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": format!("Approval {} not found", id) }))
+    }
+}
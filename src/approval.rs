@@ -0,0 +1,259 @@
+//! Human-in-the-loop approval of flagged tool calls.
+//!
+//! A call to a tool with `require_approval` is parked until a person approves
+//! or rejects it through the admin endpoint, or until the approval times out.
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
+
+use crate::types::ApprovalConfig;
+
+/// A tool call waiting for a decision
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRecord {
+    pub id: String,
+    pub server: String,
+    pub tool: String,
+    pub arguments: Option<HashMap<String, Value>>,
+    /// Unix timestamp in seconds
+    pub requested_at: u64,
+    /// Unix timestamp in seconds after which the call is rejected
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalDecision {
+    Approved,
+    Rejected(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalOutcome {
+    Approved,
+    Rejected(Option<String>),
+    TimedOut,
+}
+
+type Pending = HashMap<String, (ApprovalRecord, oneshot::Sender<ApprovalDecision>)>;
+
+pub struct ApprovalManager {
+    config: ApprovalConfig,
+    pending: Mutex<Pending>,
+    http: reqwest::Client,
+}
+
+/// Withdraws a call's approval once it stops waiting, including when the
+/// caller goes away before a decision
+struct Waiting<'a> {
+    pending: &'a Mutex<Pending>,
+    id: &'a str,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let removed = self
+            .pending
+            .lock()
+            .ok()
+            .and_then(|mut pending| pending.remove(self.id));
+        if removed.is_some() {
+            info!("Approval {} withdrawn, the caller stopped waiting", self.id);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ApprovalManager {
+    pub fn new(config: ApprovalConfig) -> Self {
+        Self {
+            config,
+            pending: Mutex::new(HashMap::new()),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Park a tool call until it is approved, rejected or times out
+    pub async fn request(
+        &self,
+        server: &str,
+        tool: &str,
+        arguments: Option<HashMap<String, Value>>,
+    ) -> ApprovalOutcome {
+        let now = unix_now();
+        let record = ApprovalRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            server: server.to_string(),
+            tool: tool.to_string(),
+            arguments,
+            requested_at: now,
+            expires_at: now + self.config.timeout,
+        };
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(record.id.clone(), (record.clone(), tx));
+        }
+        info!(
+            "Tool {} on server {} is waiting for approval {}",
+            tool, server, record.id
+        );
+        self.notify("approval_requested", &record, None);
+
+        let _waiting = Waiting {
+            pending: &self.pending,
+            id: &record.id,
+        };
+        let outcome = match tokio::time::timeout(Duration::from_secs(self.config.timeout), rx).await
+        {
+            Ok(Ok(ApprovalDecision::Approved)) => ApprovalOutcome::Approved,
+            Ok(Ok(ApprovalDecision::Rejected(reason))) => ApprovalOutcome::Rejected(reason),
+            Ok(Err(_)) | Err(_) => ApprovalOutcome::TimedOut,
+        };
+        if outcome == ApprovalOutcome::TimedOut {
+            if let Ok(mut pending) = self.pending.lock() {
+                pending.remove(&record.id);
+            }
+            warn!("Approval {} timed out", record.id);
+            self.notify("approval_timed_out", &record, None);
+        }
+        outcome
+    }
+
+    /// Calls currently waiting for a decision
+    pub async fn pending(&self) -> Vec<ApprovalRecord> {
+        let mut records: Vec<ApprovalRecord> = self
+            .pending
+            .lock()
+            .map(|pending| pending.values().map(|(record, _)| record.clone()).collect())
+            .unwrap_or_default();
+        records.sort_by_key(|record| record.requested_at);
+        records
+    }
+
+    /// Resolve a pending call, returns false if no call with `id` is waiting
+    pub async fn decide(&self, id: &str, decision: ApprovalDecision) -> bool {
+        let removed = self
+            .pending
+            .lock()
+            .ok()
+            .and_then(|mut pending| pending.remove(id));
+        let Some((record, tx)) = removed else {
+            return false;
+        };
+        let (event, reason) = match &decision {
+            ApprovalDecision::Approved => ("approval_approved", None),
+            ApprovalDecision::Rejected(reason) => ("approval_rejected", reason.clone()),
+        };
+        info!("Approval {} decided: {}", id, event);
+        self.notify(event, &record, reason);
+        tx.send(decision).is_ok()
+    }
+
+    /// Post an approval event to the configured webhook in the background
+    fn notify(&self, event: &str, record: &ApprovalRecord, reason: Option<String>) {
+        let Some(webhook) = self.config.webhook.clone() else {
+            return;
+        };
+        let body = json!({ "event": event, "approval": record, "reason": reason });
+        let mut request = self.http.post(webhook).json(&body);
+        for (key, value) in self.config.webhook_headers.iter().flatten() {
+            request = request.header(key, value);
+        }
+        tokio::spawn(async move {
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => {}
+                Err(e) => error!("Failed to deliver approval webhook: {:?}", e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn manager(timeout: u64) -> Arc<ApprovalManager> {
+        Arc::new(ApprovalManager::new(ApprovalConfig {
+            timeout,
+            ..Default::default()
+        }))
+    }
+
+    /// Wait until a call is parked and return its approval id
+    async fn parked(manager: &ApprovalManager) -> String {
+        loop {
+            if let Some(record) = manager.pending().await.first() {
+                return record.id.clone();
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn approved_calls_proceed() {
+        let manager = manager(60);
+        let waiting = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.request("files", "write", None).await }
+        });
+        let id = parked(&manager).await;
+        assert!(manager.decide(&id, ApprovalDecision::Approved).await);
+        assert_eq!(waiting.await.unwrap(), ApprovalOutcome::Approved);
+        assert!(manager.pending().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejections_carry_their_reason() {
+        let manager = manager(60);
+        let waiting = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.request("files", "write", None).await }
+        });
+        let id = parked(&manager).await;
+        let reason = Some("not today".to_string());
+        assert!(
+            manager
+                .decide(&id, ApprovalDecision::Rejected(reason.clone()))
+                .await
+        );
+        assert_eq!(waiting.await.unwrap(), ApprovalOutcome::Rejected(reason));
+    }
+
+    #[tokio::test]
+    async fn unknown_approvals_cannot_be_decided() {
+        let manager = manager(60);
+        assert!(!manager.decide("missing", ApprovalDecision::Approved).await);
+    }
+
+    #[tokio::test]
+    async fn calls_time_out_without_a_decision() {
+        let manager = manager(0);
+        let outcome = manager.request("files", "write", None).await;
+        assert_eq!(outcome, ApprovalOutcome::TimedOut);
+        assert!(manager.pending().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn approvals_are_withdrawn_when_the_caller_goes_away() {
+        let manager = manager(60);
+        let waiting = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.request("files", "write", None).await }
+        });
+        let id = parked(&manager).await;
+        waiting.abort();
+        let _ = waiting.await;
+        assert!(manager.pending().await.is_empty());
+        assert!(!manager.decide(&id, ApprovalDecision::Approved).await);
+    }
+}
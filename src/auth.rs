@@ -0,0 +1,287 @@
+//! Authentication of clients connecting to the proxy.
+//!
+//! Every request must carry `Authorization: Bearer <token>`, where the token
+//! is the configured shared secret, an HS256 JWT signed with the configured
+//! secret, or the secret of an API key in the keys file. The identity a
+//! request authenticated as is stored in its extensions.
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde_json::{json, Value};
+use std::fmt;
+use subtle::ConstantTimeEq;
+use tracing::debug;
+
+use crate::keys::{KeyScope, KeyStore};
+use crate::types::ProxyTransportAuth;
+
+/// Who a client authenticated as
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    /// API key name or JWT `sub`, none for the shared secret
+    pub subject: Option<String>,
+    /// Groups from the JWT `groups` claim
+    pub groups: Vec<String>,
+    /// Scope of the API key the client authenticated with
+    pub key: Option<KeyScope>,
+}
+
+impl Identity {
+    pub fn from_claims(claims: &Value) -> Self {
+        let groups = match claims.get("groups") {
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .filter_map(|group| group.as_str().map(str::to_string))
+                .collect(),
+            Some(Value::String(group)) => vec![group.clone()],
+            _ => Vec::new(),
+        };
+        Self {
+            subject: claims
+                .get("sub")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            groups,
+            key: None,
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.subject.as_deref().unwrap_or("anonymous"))
+    }
+}
+
+/// Why a request was not authenticated
+#[derive(Debug, Clone, Copy)]
+pub enum AuthError {
+    /// No bearer token was sent
+    Missing,
+    Invalid(&'static str),
+}
+
+pub enum Authenticator {
+    Bearer(String),
+    JwtSecret(String),
+    Keys(KeyStore),
+}
+
+impl Authenticator {
+    pub fn new(auth: ProxyTransportAuth) -> Self {
+        match auth {
+            ProxyTransportAuth::Bearer(secret) => Self::Bearer(secret),
+            ProxyTransportAuth::JwtSecret(secret) => Self::JwtSecret(secret),
+            ProxyTransportAuth::KeysFile(path) => Self::Keys(KeyStore::new(&path)),
+        }
+    }
+
+    /// Check the value of an `Authorization` header, returning who sent it
+    pub fn verify(&self, header: Option<&str>) -> Result<Identity, AuthError> {
+        let token = header
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(AuthError::Missing)?;
+        match self {
+            Self::Bearer(secret) => {
+                if bool::from(token.as_bytes().ct_eq(secret.as_bytes())) {
+                    Ok(Identity::default())
+                } else {
+                    Err(AuthError::Invalid("Invalid bearer token"))
+                }
+            }
+            Self::JwtSecret(secret) => decode::<Value>(
+                token,
+                &DecodingKey::from_secret(secret.as_bytes()),
+                &Validation::new(Algorithm::HS256),
+            )
+            .map(|data| Identity::from_claims(&data.claims))
+            .map_err(|e| {
+                debug!("Rejected JWT: {}", e);
+                AuthError::Invalid("Invalid or expired token")
+            }),
+            Self::Keys(store) => match store.find(token) {
+                Some(key) if key.is_expired() => {
+                    debug!("Rejected expired API key {}", key.name);
+                    Err(AuthError::Invalid("Invalid or expired token"))
+                }
+                Some(key) => Ok(Identity {
+                    subject: Some(key.name),
+                    groups: Vec::new(),
+                    key: Some(key.scope),
+                }),
+                None => Err(AuthError::Invalid("Invalid or expired token")),
+            },
+        }
+    }
+}
+
+fn unauthorized(error: AuthError) -> HttpResponse {
+    match error {
+        // Clients are told how to authenticate but not given an error code
+        AuthError::Missing => HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer realm=\"mcp-proxy\""))
+            .json(json!({ "error": "unauthorized", "error_description": "Missing bearer token" })),
+        AuthError::Invalid(description) => HttpResponse::Unauthorized()
+            .insert_header((
+                "WWW-Authenticate",
+                format!(
+                    "Bearer realm=\"mcp-proxy\", error=\"invalid_token\", error_description=\"{}\"",
+                    description
+                ),
+            ))
+            .json(json!({ "error": "invalid_token", "error_description": description })),
+    }
+}
+
+/// Middleware rejecting requests that fail the `Authenticator` in the app data.
+///
+/// Requests pass unchecked when no authenticator is configured.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(authenticator) = req.app_data::<web::Data<Authenticator>>() else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    };
+    let header = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok());
+    match authenticator.verify(header) {
+        Ok(identity) => {
+            req.extensions_mut().insert(identity);
+            next.call(req)
+                .await
+                .map(ServiceResponse::map_into_boxed_body)
+        }
+        Err(error) => Ok(req.into_response(unauthorized(error))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const SECRET: &str = "jwt-secret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn jwt(algorithm: Algorithm, secret: &str, claims: Value) -> String {
+        encode(
+            &Header::new(algorithm),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn bearer(token: &str) -> String {
+        format!("Bearer {}", token)
+    }
+
+    fn rejected(result: Result<Identity, AuthError>) -> bool {
+        matches!(result, Err(AuthError::Invalid("Invalid or expired token")))
+    }
+
+    #[test]
+    fn shared_secret_must_match_exactly() {
+        let auth = Authenticator::Bearer("s3cret".to_string());
+        assert_eq!(
+            auth.verify(Some("Bearer s3cret")).unwrap(),
+            Identity::default()
+        );
+        assert!(auth.verify(Some("Bearer s3cre")).is_err());
+        assert!(auth.verify(Some("Bearer s3cret2")).is_err());
+        assert!(auth.verify(Some("Bearer ")).is_err());
+    }
+
+    #[test]
+    fn missing_or_malformed_headers_are_rejected() {
+        let auth = Authenticator::Bearer("s3cret".to_string());
+        assert!(matches!(auth.verify(None), Err(AuthError::Missing)));
+        assert!(matches!(
+            auth.verify(Some("Basic s3cret")),
+            Err(AuthError::Missing)
+        ));
+        assert!(matches!(
+            auth.verify(Some("s3cret")),
+            Err(AuthError::Missing)
+        ));
+    }
+
+    #[test]
+    fn valid_jwts_carry_subject_and_groups() {
+        let auth = Authenticator::JwtSecret(SECRET.to_string());
+        let token = jwt(
+            Algorithm::HS256,
+            SECRET,
+            json!({ "sub": "alice", "groups": ["ops", 7, "dev"], "exp": now() + 600 }),
+        );
+        let identity = auth.verify(Some(&bearer(&token))).unwrap();
+        assert_eq!(identity.subject.as_deref(), Some("alice"));
+        assert_eq!(identity.groups, vec!["ops", "dev"]);
+        assert!(identity.key.is_none());
+    }
+
+    #[test]
+    fn expired_jwts_are_rejected() {
+        let auth = Authenticator::JwtSecret(SECRET.to_string());
+        let token = jwt(
+            Algorithm::HS256,
+            SECRET,
+            json!({ "sub": "alice", "exp": now() - 3600 }),
+        );
+        assert!(rejected(auth.verify(Some(&bearer(&token)))));
+
+        let without_expiry = jwt(Algorithm::HS256, SECRET, json!({ "sub": "alice" }));
+        assert!(rejected(auth.verify(Some(&bearer(&without_expiry)))));
+    }
+
+    #[test]
+    fn jwts_with_another_secret_or_algorithm_are_rejected() {
+        let auth = Authenticator::JwtSecret(SECRET.to_string());
+        let claims = json!({ "sub": "alice", "exp": now() + 600 });
+        let wrong_secret = jwt(Algorithm::HS256, "other-secret", claims.clone());
+        assert!(rejected(auth.verify(Some(&bearer(&wrong_secret)))));
+
+        let wrong_algorithm = jwt(Algorithm::HS384, SECRET, claims);
+        assert!(rejected(auth.verify(Some(&bearer(&wrong_algorithm)))));
+
+        // {"alg":"none"} with no signature
+        let unsigned = "eyJhbGciOiJub25lIiwidHlwIjoiSldUIn0.\
+                        eyJzdWIiOiJhZG1pbiIsImV4cCI6OTk5OTk5OTk5OX0.";
+        assert!(rejected(auth.verify(Some(&bearer(unsigned)))));
+    }
+
+    #[test]
+    fn tampered_or_garbage_jwts_are_rejected() {
+        let auth = Authenticator::JwtSecret(SECRET.to_string());
+        let token = jwt(
+            Algorithm::HS256,
+            SECRET,
+            json!({ "sub": "alice", "exp": now() + 600 }),
+        );
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let forged = jwt(
+            Algorithm::HS256,
+            "other-secret",
+            json!({ "sub": "admin", "exp": now() + 600 }),
+        );
+        parts[1] = forged.split('.').nth(1).unwrap();
+        assert!(rejected(auth.verify(Some(&bearer(&parts.join("."))))));
+        assert!(rejected(auth.verify(Some("Bearer not-a-jwt"))));
+    }
+}
@@ -0,0 +1,224 @@
+//! Endpoint selection and health tracking for server groups.
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::types::{BalanceStrategy, GroupHealthConfig};
+
+#[derive(Default)]
+struct EndpointHealth {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+struct Endpoint {
+    in_flight: AtomicUsize,
+    health: Mutex<EndpointHealth>,
+}
+
+/// Snapshot of an endpoint for status output
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStatus {
+    pub index: usize,
+    pub in_flight: usize,
+    pub consecutive_failures: u32,
+    pub ejected: bool,
+}
+
+pub struct Balancer {
+    name: String,
+    strategy: BalanceStrategy,
+    health: GroupHealthConfig,
+    endpoints: Vec<Endpoint>,
+    next: AtomicUsize,
+}
+
+/// Tracks a call in flight on an endpoint until dropped
+pub struct InFlight<'a> {
+    endpoint: &'a Endpoint,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.endpoint.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Balancer {
+    pub fn new(
+        name: &str,
+        strategy: BalanceStrategy,
+        health: GroupHealthConfig,
+        endpoints: usize,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            strategy,
+            health,
+            endpoints: (0..endpoints)
+                .map(|_| Endpoint {
+                    in_flight: AtomicUsize::new(0),
+                    health: Mutex::new(EndpointHealth::default()),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn is_ejected(&self, index: usize, now: Instant) -> bool {
+        self.endpoints[index]
+            .health
+            .lock()
+            .map(|h| h.ejected_until.is_some_and(|until| until > now))
+            .unwrap_or(false)
+    }
+
+    /// Endpoint indexes in the order they should be tried.
+    ///
+    /// Ejected endpoints are skipped unless every endpoint is ejected, in which
+    /// case all of them are tried rather than failing outright.
+    pub fn candidates(&self) -> Vec<usize> {
+        let count = self.endpoints.len();
+        let mut order: Vec<usize> = match self.strategy {
+            BalanceStrategy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::SeqCst) % count.max(1);
+                (0..count).map(|i| (start + i) % count).collect()
+            }
+            BalanceStrategy::LeastInFlight => {
+                let mut order: Vec<usize> = (0..count).collect();
+                order.sort_by_key(|&i| self.endpoints[i].in_flight.load(Ordering::SeqCst));
+                order
+            }
+            BalanceStrategy::Priority => (0..count).collect(),
+        };
+
+        let now = Instant::now();
+        if order.iter().any(|&i| !self.is_ejected(i, now)) {
+            order.retain(|&i| !self.is_ejected(i, now));
+        }
+        order
+    }
+
+    pub fn start(&self, index: usize) -> InFlight<'_> {
+        let endpoint = &self.endpoints[index];
+        endpoint.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight { endpoint }
+    }
+
+    pub fn record_success(&self, index: usize) {
+        if let Ok(mut health) = self.endpoints[index].health.lock() {
+            health.consecutive_failures = 0;
+            health.ejected_until = None;
+        }
+    }
+
+    pub fn record_failure(&self, index: usize) {
+        let Ok(mut health) = self.endpoints[index].health.lock() else {
+            return;
+        };
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.health.failure_threshold {
+            warn!(
+                "Ejecting endpoint {} of group {} for {}s after {} consecutive failures",
+                index, self.name, self.health.ejection_secs, health.consecutive_failures
+            );
+            health.consecutive_failures = 0;
+            health.ejected_until =
+                Some(Instant::now() + Duration::from_secs(self.health.ejection_secs));
+        }
+    }
+
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        let now = Instant::now();
+        self.endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| EndpointStatus {
+                index,
+                in_flight: endpoint.in_flight.load(Ordering::SeqCst),
+                consecutive_failures: endpoint
+                    .health
+                    .lock()
+                    .map(|h| h.consecutive_failures)
+                    .unwrap_or_default(),
+                ejected: self.is_ejected(index, now),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balancer(strategy: BalanceStrategy, ejection_secs: u64) -> Balancer {
+        let health = GroupHealthConfig {
+            failure_threshold: 2,
+            ejection_secs,
+        };
+        Balancer::new("group", strategy, health, 3)
+    }
+
+    #[test]
+    fn round_robin_rotates_the_first_endpoint() {
+        let balancer = balancer(BalanceStrategy::RoundRobin, 30);
+        assert_eq!(balancer.candidates(), vec![0, 1, 2]);
+        assert_eq!(balancer.candidates(), vec![1, 2, 0]);
+        assert_eq!(balancer.candidates(), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn least_in_flight_prefers_idle_endpoints() {
+        let balancer = balancer(BalanceStrategy::LeastInFlight, 30);
+        let _first = balancer.start(0);
+        let _second = balancer.start(0);
+        let _third = balancer.start(1);
+        assert_eq!(balancer.candidates(), vec![2, 1, 0]);
+        drop(_third);
+        assert_eq!(balancer.candidates()[0], 1);
+    }
+
+    #[test]
+    fn failing_endpoints_are_ejected_after_the_threshold() {
+        let balancer = balancer(BalanceStrategy::Priority, 30);
+        balancer.record_failure(0);
+        assert_eq!(balancer.candidates(), vec![0, 1, 2]);
+        balancer.record_failure(0);
+        assert_eq!(balancer.candidates(), vec![1, 2]);
+        assert!(balancer.status()[0].ejected);
+
+        balancer.record_success(0);
+        assert_eq!(balancer.candidates(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn successes_reset_the_failure_count() {
+        let balancer = balancer(BalanceStrategy::Priority, 30);
+        balancer.record_failure(0);
+        balancer.record_success(0);
+        balancer.record_failure(0);
+        assert_eq!(balancer.candidates(), vec![0, 1, 2]);
+        assert_eq!(balancer.status()[0].consecutive_failures, 1);
+    }
+
+    #[test]
+    fn every_endpoint_is_tried_when_all_are_ejected() {
+        let balancer = balancer(BalanceStrategy::Priority, 30);
+        for index in 0..3 {
+            balancer.record_failure(index);
+            balancer.record_failure(index);
+        }
+        assert_eq!(balancer.candidates(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn ejection_expires() {
+        let balancer = balancer(BalanceStrategy::Priority, 0);
+        balancer.record_failure(0);
+        balancer.record_failure(0);
+        assert_eq!(balancer.candidates(), vec![0, 1, 2]);
+        assert!(!balancer.status()[0].ejected);
+    }
+}
@@ -0,0 +1,239 @@
+//! Per-server circuit breakers that fail calls fast while an upstream is down.
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::retry;
+use crate::types::CircuitBreakerConfig;
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A single probe call is in flight since `started`
+    HalfOpen {
+        started: Instant,
+    },
+}
+
+/// Snapshot of a breaker for status output
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerStatus {
+    /// `closed`, `open` or `half_open`
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+    /// Seconds until an open breaker lets a probe through
+    pub retry_after: Option<u64>,
+}
+
+pub struct CircuitBreaker {
+    server: String,
+    config: CircuitBreakerConfig,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(server: &str, config: CircuitBreakerConfig) -> Self {
+        Self {
+            server: server.to_string(),
+            config,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    fn open_for(&self) -> Duration {
+        Duration::from_secs(self.config.open_secs)
+    }
+
+    /// Check whether a call may be sent, returning the time left until the
+    /// next probe when the breaker rejects it.
+    ///
+    /// Once an open breaker expires the first caller becomes the half-open
+    /// probe. A probe that never reports back is replaced after `open_secs`.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let Ok(mut state) = self.state.lock() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if until > now => Err(until - now),
+            State::HalfOpen { started } if started + self.open_for() > now => {
+                Err(started + self.open_for() - now)
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                info!(
+                    "Circuit breaker for server {} is half-open, sending a probe",
+                    self.server
+                );
+                *state = State::HalfOpen { started: now };
+                Ok(())
+            }
+        }
+    }
+
+    /// Record the outcome of a call let through by `try_acquire`.
+    ///
+    /// JSON-RPC errors mean the server is up, so only timeouts and transport
+    /// failures count against it.
+    pub fn record<T>(&self, result: &anyhow::Result<T>) {
+        let failed = match result {
+            Ok(_) => false,
+            Err(e) => retry::is_failure(e),
+        };
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        *state = match (*state, failed) {
+            (State::Closed { .. }, false) => State::Closed { failures: 0 },
+            (_, false) => {
+                info!("Circuit breaker for server {} is closed", self.server);
+                State::Closed { failures: 0 }
+            }
+            (State::Closed { failures }, true) if failures + 1 < self.config.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, true) => {
+                warn!(
+                    "Circuit breaker for server {} is open for {}s",
+                    self.server, self.config.open_secs
+                );
+                State::Open {
+                    until: Instant::now() + self.open_for(),
+                }
+            }
+        };
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let state = self
+            .state
+            .lock()
+            .map(|state| *state)
+            .unwrap_or(State::Closed { failures: 0 });
+        match state {
+            State::Closed { failures } => BreakerStatus {
+                state: "closed",
+                consecutive_failures: failures,
+                retry_after: None,
+            },
+            State::Open { until } => BreakerStatus {
+                state: "open",
+                consecutive_failures: self.config.failure_threshold,
+                retry_after: Some(
+                    until
+                        .saturating_duration_since(Instant::now())
+                        .as_secs_f64()
+                        .ceil() as u64,
+                ),
+            },
+            State::HalfOpen { .. } => BreakerStatus {
+                state: "half_open",
+                consecutive_failures: self.config.failure_threshold,
+                retry_after: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn breaker(open_secs: u64) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "files",
+            CircuitBreakerConfig {
+                failure_threshold: 2,
+                open_secs,
+            },
+        )
+    }
+
+    fn failure() -> anyhow::Result<()> {
+        Err(retry::UpstreamError::Timeout(Duration::from_secs(1)).into())
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(60);
+        breaker.record(&failure());
+        assert_eq!(breaker.status().state, "closed");
+        assert!(breaker.try_acquire().is_ok());
+        breaker.record(&failure());
+
+        let status = breaker.status();
+        assert_eq!(status.state, "open");
+        assert_eq!(status.retry_after, Some(60));
+        assert!(breaker.try_acquire().is_err());
+    }
+
+    #[test]
+    fn successes_reset_the_failure_count() {
+        let breaker = breaker(60);
+        breaker.record(&failure());
+        breaker.record(&Ok(()));
+        breaker.record(&failure());
+        assert_eq!(breaker.status().state, "closed");
+        assert_eq!(breaker.status().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn upstream_and_proxy_errors_are_not_failures() {
+        let breaker = breaker(60);
+        for _ in 0..3 {
+            breaker.record::<()>(&Err(retry::UpstreamError::Rejected(
+                "Request failed: invalid params".to_string(),
+            )
+            .into()));
+            breaker.record::<()>(&Err(anyhow!("Broken pipe")));
+            breaker.record::<()>(&Err(anyhow::Error::new(retry::ProxyError(
+                "Proxy is shutting down".to_string(),
+            ))));
+        }
+        assert_eq!(breaker.status().state, "closed");
+        assert_eq!(breaker.status().consecutive_failures, 0);
+    }
+
+    #[test]
+    fn a_successful_probe_closes_the_breaker() {
+        let breaker = breaker(0);
+        breaker.record(&failure());
+        breaker.record(&failure());
+        assert!(breaker.try_acquire().is_ok());
+        assert_eq!(breaker.status().state, "half_open");
+        breaker.record(&Ok(()));
+        assert_eq!(breaker.status().state, "closed");
+    }
+
+    #[test]
+    fn a_failed_probe_reopens_the_breaker() {
+        let breaker = breaker(0);
+        breaker.record(&failure());
+        breaker.record(&failure());
+        assert!(breaker.try_acquire().is_ok());
+        breaker.record(&failure());
+        assert_eq!(breaker.status().state, "open");
+    }
+
+    #[test]
+    fn only_one_probe_is_let_through() {
+        let breaker = breaker(60);
+        if let Ok(mut state) = breaker.state.lock() {
+            *state = State::Open {
+                until: Instant::now(),
+            };
+        }
+        assert!(breaker.try_acquire().is_ok());
+        assert_eq!(breaker.status().state, "half_open");
+        assert!(breaker.try_acquire().is_err());
+    }
+}
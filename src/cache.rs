@@ -0,0 +1,256 @@
+//! Result cache for read-only and idempotent tool calls.
+use async_mcp::types::{CallToolRequest, CallToolResponse};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::types::{CacheConfig, ToolAnnotations};
+
+struct CacheEntry {
+    response: CallToolResponse,
+    expires_at: Instant,
+    last_used: Instant,
+}
+
+/// Hit and miss counters of the result cache
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+pub struct ResultCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: HashMap<String, CacheEntry>,
+    hits: u64,
+    misses: u64,
+}
+
+impl ResultCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl),
+            max_entries: config.max_entries,
+            entries: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Build the cache key from the server, tool, arguments and caller env vars.
+    ///
+    /// `serde_json` objects keep their keys sorted, so serializing the arguments
+    /// as a `Value` yields a canonical form.
+    pub fn key(
+        server_name: &str,
+        req: &CallToolRequest,
+        env_vars: Option<&HashMap<String, String>>,
+    ) -> String {
+        let arguments = serde_json::to_value(&req.arguments).unwrap_or(Value::Null);
+        let env_vars = serde_json::to_value(env_vars).unwrap_or(Value::Null);
+        format!("{server_name}\0{}\0{arguments}\0{env_vars}", req.name)
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<CallToolResponse> {
+        let now = Instant::now();
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > now => {
+                entry.last_used = now;
+                self.hits += 1;
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: String, response: CallToolResponse) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let now = Instant::now();
+        if self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            self.entries.retain(|_, entry| entry.expires_at > now);
+        }
+        while self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+        self.entries.insert(
+            key,
+            CacheEntry {
+                response,
+                expires_at: now + self.ttl,
+                last_used: now,
+            },
+        );
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+}
+
+/// Whether results of a tool may be cached.
+///
+/// The tool's own `cache` setting wins, then its `readOnlyHint` and
+/// `idempotentHint` annotations, then the server's `cache` setting.
+pub fn is_cacheable(
+    tool_cache: Option<bool>,
+    annotations: Option<&ToolAnnotations>,
+    server_cache: Option<bool>,
+) -> bool {
+    if let Some(cache) = tool_cache {
+        return cache;
+    }
+    let hints = annotations
+        .map(|hints| [hints.read_only_hint, hints.idempotent_hint])
+        .unwrap_or_default();
+    if hints.contains(&Some(true)) {
+        return true;
+    }
+    if hints.contains(&Some(false)) {
+        return false;
+    }
+    server_cache.unwrap_or(false)
+}
+
+/// Whether the caller asked to skip the cache with `_meta.no_cache`
+pub fn is_bypassed(req: &CallToolRequest) -> bool {
+    req.meta
+        .as_ref()
+        .and_then(|meta| meta.get("no_cache"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_mcp::types::ToolResponseContent;
+    use serde_json::json;
+
+    fn cache(ttl: u64, max_entries: usize) -> ResultCache {
+        ResultCache::new(&CacheConfig { ttl, max_entries })
+    }
+
+    fn response(text: &str) -> CallToolResponse {
+        CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: text.to_string(),
+            }],
+            is_error: None,
+            meta: None,
+        }
+    }
+
+    fn request(arguments: Value) -> CallToolRequest {
+        serde_json::from_value(json!({ "name": "fetch", "arguments": arguments })).unwrap()
+    }
+
+    /// Let `Instant::now()` move on between uses
+    fn tick() {
+        std::thread::sleep(Duration::from_millis(2));
+    }
+
+    #[test]
+    fn hits_and_misses_are_counted() {
+        let mut cache = cache(60, 10);
+        assert!(cache.get("a").is_none());
+        cache.insert("a".to_string(), response("a"));
+        assert!(cache.get("a").is_some());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut cache = cache(0, 10);
+        cache.insert("a".to_string(), response("a"));
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let mut cache = cache(60, 2);
+        cache.insert("a".to_string(), response("a"));
+        tick();
+        cache.insert("b".to_string(), response("b"));
+        tick();
+        cache.get("a");
+        tick();
+        cache.insert("c".to_string(), response("c"));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn nothing_is_kept_without_entries() {
+        let mut cache = cache(60, 0);
+        cache.insert("a".to_string(), response("a"));
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn keys_ignore_argument_order_but_not_env_vars() {
+        let first = ResultCache::key("s", &request(json!({ "a": 1, "b": 2 })), None);
+        let second = ResultCache::key("s", &request(json!({ "b": 2, "a": 1 })), None);
+        assert_eq!(first, second);
+
+        let env = HashMap::from([("TOKEN".to_string(), "x".to_string())]);
+        let with_env = ResultCache::key("s", &request(json!({ "a": 1, "b": 2 })), Some(&env));
+        assert_ne!(first, with_env);
+    }
+
+    #[test]
+    fn tool_setting_wins_then_hints_then_server() {
+        let read_only = ToolAnnotations {
+            read_only_hint: Some(true),
+            ..Default::default()
+        };
+        let not_idempotent = ToolAnnotations {
+            idempotent_hint: Some(false),
+            ..Default::default()
+        };
+        let no_hints = ToolAnnotations::default();
+
+        assert!(!is_cacheable(Some(false), Some(&read_only), Some(true)));
+        assert!(is_cacheable(Some(true), None, None));
+        assert!(is_cacheable(None, Some(&read_only), Some(false)));
+        assert!(!is_cacheable(None, Some(&not_idempotent), Some(true)));
+        assert!(is_cacheable(None, Some(&no_hints), Some(true)));
+        assert!(is_cacheable(None, None, Some(true)));
+        assert!(!is_cacheable(None, None, None));
+    }
+
+    #[test]
+    fn no_cache_meta_bypasses_the_cache() {
+        let mut req = request(json!({}));
+        assert!(!is_bypassed(&req));
+        req.meta = Some(json!({ "no_cache": true }));
+        assert!(is_bypassed(&req));
+    }
+}
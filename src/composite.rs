@@ -0,0 +1,190 @@
+//! Config-defined composite tools.
+//!
+//! A composite tool runs a sequence of upstream tool calls. Step arguments may
+//! reference the composite tool's input and the results of earlier steps using
+//! `{{ input.<path> }}` and `{{ steps.<id>.<path> }}` placeholders.
+use anyhow::{anyhow, bail, Result};
+use async_mcp::types::{CallToolResponse, Tool, ToolResponseContent};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::types::CompositeToolConfig;
+
+impl CompositeToolConfig {
+    /// Describe this composite tool the way upstream tools are listed
+    pub fn to_tool(&self, name: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: self.description.clone(),
+            input_schema: self.input_schema.clone(),
+            output_schema: None,
+        }
+    }
+}
+
+/// Expose a step's response to later steps as `text`, `content` and `is_error`
+pub fn step_result(response: &CallToolResponse) -> Value {
+    json!({
+        "text": response_text(response),
+        "content": response.content,
+        "is_error": response.is_error.unwrap_or(false),
+    })
+}
+
+/// Concatenate all text content of a response
+pub fn response_text(response: &CallToolResponse) -> String {
+    response
+        .content
+        .iter()
+        .filter_map(|c| match c {
+            ToolResponseContent::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Substitute placeholders in `template` with values looked up in `ctx`.
+///
+/// A string consisting of a single placeholder is replaced by the referenced
+/// value as-is, so objects and numbers keep their type. Placeholders embedded
+/// in a longer string are interpolated as text.
+pub fn render(template: &Value, ctx: &Value) -> Result<Value> {
+    match template {
+        Value::String(s) => render_string(s, ctx),
+        Value::Array(items) => Ok(Value::Array(
+            items
+                .iter()
+                .map(|item| render(item, ctx))
+                .collect::<Result<_>>()?,
+        )),
+        Value::Object(map) => Ok(Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), render(v, ctx)?)))
+                .collect::<Result<_>>()?,
+        )),
+        other => Ok(other.clone()),
+    }
+}
+
+fn render_string(s: &str, ctx: &Value) -> Result<Value> {
+    let trimmed = s.trim();
+    if let Some(expr) = trimmed
+        .strip_prefix("{{")
+        .and_then(|rest| rest.strip_suffix("}}"))
+    {
+        if !expr.contains("{{") {
+            return lookup(ctx, expr.trim()).cloned();
+        }
+    }
+
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow!("Unterminated placeholder in template: {}", s))?;
+        match lookup(ctx, after[..end].trim())? {
+            Value::String(v) => out.push_str(v),
+            v => out.push_str(&v.to_string()),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(Value::String(out))
+}
+
+fn lookup<'a>(ctx: &'a Value, path: &str) -> Result<&'a Value> {
+    let mut current = ctx;
+    for segment in path.split('.') {
+        current = match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("Unknown template variable: {}", path))?;
+    }
+    Ok(current)
+}
+
+/// Convert rendered step arguments into tool call arguments
+pub fn into_arguments(value: Value) -> Result<Option<HashMap<String, Value>>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Object(map) => Ok(Some(map.into_iter().collect())),
+        other => bail!("Step arguments must be an object, got {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx() -> Value {
+        json!({
+            "input": { "query": "rust", "limit": 5, "tags": ["a", "b"] },
+            "steps": { "search": { "text": "found", "is_error": false } },
+        })
+    }
+
+    #[test]
+    fn whole_placeholder_keeps_type() {
+        assert_eq!(
+            render(&json!("{{ input.limit }}"), &ctx()).unwrap(),
+            json!(5)
+        );
+        assert_eq!(
+            render(&json!("{{input.tags}}"), &ctx()).unwrap(),
+            json!(["a", "b"])
+        );
+    }
+
+    #[test]
+    fn embedded_placeholders_are_interpolated() {
+        let rendered = render(
+            &json!("{{ input.query }} x{{ input.limit }}: {{ steps.search.text }}"),
+            &ctx(),
+        )
+        .unwrap();
+        assert_eq!(rendered, json!("rust x5: found"));
+    }
+
+    #[test]
+    fn renders_nested_values_and_array_indices() {
+        let template = json!({
+            "q": "{{ input.query }}",
+            "first": ["{{ input.tags.0 }}", 1, null],
+        });
+        assert_eq!(
+            render(&template, &ctx()).unwrap(),
+            json!({ "q": "rust", "first": ["a", 1, null] })
+        );
+    }
+
+    #[test]
+    fn unknown_variables_fail() {
+        let error = render(&json!("{{ input.missing }}"), &ctx()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown template variable: input.missing"
+        );
+        assert!(render(&json!("{{ input.tags.7 }}"), &ctx()).is_err());
+    }
+
+    #[test]
+    fn unterminated_placeholders_fail() {
+        assert!(render(&json!("a {{ input.query"), &ctx()).is_err());
+    }
+
+    #[test]
+    fn arguments_must_be_an_object() {
+        assert_eq!(into_arguments(Value::Null).unwrap(), None);
+        assert_eq!(
+            into_arguments(json!({ "a": 1 })).unwrap(),
+            Some(HashMap::from([("a".to_string(), json!(1))]))
+        );
+        assert!(into_arguments(json!([1])).is_err());
+    }
+}
@@ -0,0 +1,409 @@
+//! Credentials for upstream servers.
+//!
+//! Static tokens are sent as configured. Tokens from an OAuth2 token endpoint
+//! or a helper command are cached and fetched again shortly before they
+//! expire. Failed fetches are not repeated before a growing backoff.
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::retry::ProxyError;
+use crate::types::UpstreamAuth;
+
+/// How long before expiry a token is replaced, at most
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+/// A token is used at least this long, however short-lived it claims to be
+const MIN_REFRESH: Duration = Duration::from_secs(10);
+/// Longest wait after failed fetches before fetching again
+const MAX_FETCH_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A token response, both from OAuth2 and from helper commands that print JSON
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+struct Token {
+    value: String,
+    /// When to fetch a new token, never for tokens without an expiry
+    refresh_at: Option<Instant>,
+}
+
+impl Token {
+    /// A token replaced before its `lifetime` runs out. A lifetime of zero
+    /// says as little about the expiry as a missing one.
+    fn new(value: String, lifetime: Option<Duration>) -> Self {
+        let refresh_in = lifetime
+            .filter(|lifetime| !lifetime.is_zero())
+            .map(|lifetime| {
+                lifetime
+                    .saturating_sub(REFRESH_MARGIN)
+                    .max(lifetime / 2)
+                    .max(MIN_REFRESH)
+            });
+        Self {
+            value,
+            refresh_at: refresh_in.map(|refresh_in| Instant::now() + refresh_in),
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.refresh_at
+            .is_none_or(|refresh_at| Instant::now() < refresh_at)
+    }
+}
+
+/// Wait after `failures` failed fetches in a row, doubling from a second
+fn fetch_backoff(failures: u32) -> Duration {
+    Duration::from_secs(1 << failures.saturating_sub(1).min(6)).min(MAX_FETCH_BACKOFF)
+}
+
+/// Fetches that failed in a row, and when to try again
+struct Failure {
+    count: u32,
+    retry_at: Instant,
+    error: String,
+}
+
+#[derive(Default)]
+struct Cached {
+    token: Option<Token>,
+    failure: Option<Failure>,
+}
+
+pub struct Credentials {
+    name: String,
+    auth: UpstreamAuth,
+    http: reqwest::Client,
+    /// Limit on fetching a token, from an endpoint or a helper command
+    timeout: Duration,
+    cached: Mutex<Cached>,
+}
+
+impl Credentials {
+    pub fn new(name: &str, auth: UpstreamAuth, timeout: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            auth,
+            http: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .unwrap_or_default(),
+            timeout,
+            cached: Mutex::new(Cached::default()),
+        }
+    }
+
+    /// The value of the `Authorization` header, fetching a new token when the
+    /// cached one is about to expire
+    pub async fn authorization(&self) -> Result<String> {
+        match &self.auth {
+            UpstreamAuth::Bearer { token } => Ok(format!("Bearer {}", token)),
+            _ => {
+                let mut cached = self.cached.lock().await;
+                if let Some(token) = cached.token.as_ref().filter(|token| token.is_fresh()) {
+                    return Ok(format!("Bearer {}", token.value));
+                }
+                // Without a token the server is never reached, the proxy failed
+                if let Some(failure) = &cached.failure {
+                    let wait = failure.retry_at.saturating_duration_since(Instant::now());
+                    if !wait.is_zero() {
+                        return Err(ProxyError(format!(
+                            "Failed to get a token for server {}: {}, retrying in {}s",
+                            self.name,
+                            failure.error,
+                            wait.as_secs_f64().ceil()
+                        ))
+                        .into());
+                    }
+                }
+                match self.fetch().await {
+                    Ok(fetched) => {
+                        let value = format!("Bearer {}", fetched.value);
+                        *cached = Cached {
+                            token: Some(fetched),
+                            failure: None,
+                        };
+                        Ok(value)
+                    }
+                    Err(e) => {
+                        let count = cached.failure.as_ref().map_or(1, |f| f.count + 1);
+                        let backoff = fetch_backoff(count);
+                        let error = format!("{:#}", e);
+                        warn!(
+                            "Failed to get a token for server {}, retrying in {:?}: {}",
+                            self.name, backoff, error
+                        );
+                        cached.failure = Some(Failure {
+                            count,
+                            retry_at: Instant::now() + backoff,
+                            error: error.clone(),
+                        });
+                        Err(ProxyError(format!(
+                            "Failed to get a token for server {}: {}",
+                            self.name, error
+                        ))
+                        .into())
+                    }
+                }
+            }
+        }
+    }
+
+    /// When the current token has to be replaced, none if it never has to
+    pub async fn refresh_at(&self) -> Option<Instant> {
+        self.cached
+            .lock()
+            .await
+            .token
+            .as_ref()
+            .and_then(|token| token.refresh_at)
+    }
+
+    async fn fetch(&self) -> Result<Token> {
+        let token = match &self.auth {
+            UpstreamAuth::OAuth2 {
+                token_url,
+                client_id,
+                client_secret,
+                scope,
+                audience,
+            } => {
+                let mut form = vec![
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id.as_str()),
+                    ("client_secret", client_secret.as_str()),
+                ];
+                if let Some(scope) = scope {
+                    form.push(("scope", scope));
+                }
+                if let Some(audience) = audience {
+                    form.push(("audience", audience));
+                }
+                let response = self.http.post(token_url).form(&form).send().await?;
+                let status = response.status();
+                if !status.is_success() {
+                    let text = response.text().await.unwrap_or_default();
+                    anyhow::bail!("Token endpoint answered {status}: {text}");
+                }
+                let response: TokenResponse = response.json().await?;
+                Token::new(
+                    response.access_token,
+                    response.expires_in.map(Duration::from_secs),
+                )
+            }
+            UpstreamAuth::Command {
+                command,
+                args,
+                refresh_secs,
+            } => {
+                let output = tokio::process::Command::new(command)
+                    .args(args)
+                    .stdin(std::process::Stdio::null())
+                    .kill_on_drop(true)
+                    .output();
+                let output = tokio::time::timeout(self.timeout, output)
+                    .await
+                    .map_err(|_| {
+                        anyhow::anyhow!(
+                            "{} did not finish within {}s",
+                            command,
+                            self.timeout.as_secs()
+                        )
+                    })??;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "{} exited with {}: {}",
+                        command,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                let stdout = String::from_utf8(output.stdout)?;
+                let stdout = stdout.trim();
+                match serde_json::from_str::<TokenResponse>(stdout) {
+                    Ok(response) => Token::new(
+                        response.access_token,
+                        response
+                            .expires_in
+                            .or(*refresh_secs)
+                            .map(Duration::from_secs),
+                    ),
+                    Err(_) if !stdout.is_empty() => {
+                        Token::new(stdout.to_string(), refresh_secs.map(Duration::from_secs))
+                    }
+                    Err(_) => anyhow::bail!("{} printed no token", command),
+                }
+            }
+            UpstreamAuth::Bearer { .. } => anyhow::bail!("Static credentials are not fetched"),
+        };
+        info!("Obtained a new token for server {}", self.name);
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Seconds until a token is refreshed, none if never
+    fn refresh_in(token: &Token) -> Option<u64> {
+        token.refresh_at.map(|at| {
+            at.saturating_duration_since(Instant::now())
+                .as_secs_f64()
+                .round() as u64
+        })
+    }
+
+    async fn cached_refresh_in(credentials: &Credentials) -> Option<u64> {
+        refresh_in(credentials.cached.lock().await.token.as_ref()?)
+    }
+
+    fn command(script: &str, refresh_secs: Option<u64>) -> Credentials {
+        let auth = UpstreamAuth::Command {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            refresh_secs,
+        };
+        Credentials::new("test", auth, Duration::from_secs(5))
+    }
+
+    /// A token endpoint answering every request with `status` and `body`,
+    /// with the number of requests it got
+    async fn token_endpoint(
+        status: &'static str,
+        body: &'static str,
+    ) -> (Credentials, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                // The form is small, the request ends with its last field
+                while !String::from_utf8_lossy(&request).contains("client_secret=") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buffer[..n]),
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        let auth = UpstreamAuth::OAuth2 {
+            token_url: url,
+            client_id: "proxy".to_string(),
+            client_secret: "secret".to_string(),
+            scope: None,
+            audience: None,
+        };
+        (
+            Credentials::new("test", auth, Duration::from_secs(5)),
+            requests,
+        )
+    }
+
+    #[test]
+    fn tokens_are_refreshed_before_they_expire() {
+        let refresh =
+            |secs| refresh_in(&Token::new(String::new(), Some(Duration::from_secs(secs))));
+        assert_eq!(refresh(3600), Some(3540));
+        assert_eq!(refresh(100), Some(50));
+        assert_eq!(refresh(30), Some(15));
+        // Never sooner than MIN_REFRESH, however short the lifetime
+        assert_eq!(refresh(4), Some(10));
+        assert_eq!(refresh(1), Some(10));
+    }
+
+    #[test]
+    fn tokens_without_a_known_expiry_are_kept() {
+        let token = Token::new(String::new(), Some(Duration::ZERO));
+        assert_eq!(refresh_in(&token), None);
+        assert!(token.is_fresh());
+        assert_eq!(refresh_in(&Token::new(String::new(), None)), None);
+    }
+
+    #[test]
+    fn fetch_backoff_doubles_up_to_a_minute() {
+        let backoff: Vec<u64> = (1..=8).map(|n| fetch_backoff(n).as_secs()).collect();
+        assert_eq!(backoff, [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[tokio::test]
+    async fn commands_print_json_or_a_bare_token() {
+        let json = command(
+            r#"echo '{"access_token":"abc","expires_in":120}'"#,
+            Some(600),
+        );
+        assert_eq!(json.authorization().await.unwrap(), "Bearer abc");
+        assert_eq!(cached_refresh_in(&json).await, Some(60));
+
+        let bare = command("echo '  raw-token  '", None);
+        assert_eq!(bare.authorization().await.unwrap(), "Bearer raw-token");
+        assert_eq!(cached_refresh_in(&bare).await, None);
+
+        // `refresh_secs` stands in for a missing `expires_in`
+        let json_without_expiry = command(r#"echo '{"access_token":"abc"}'"#, Some(600));
+        json_without_expiry.authorization().await.unwrap();
+        assert_eq!(cached_refresh_in(&json_without_expiry).await, Some(540));
+    }
+
+    #[tokio::test]
+    async fn failing_commands_are_proxy_errors() {
+        let error = command("echo denied >&2; exit 2", None)
+            .authorization()
+            .await
+            .unwrap_err();
+        assert!(error.is::<ProxyError>());
+        assert!(error.to_string().contains("denied"), "{}", error);
+
+        let error = command("true", None).authorization().await.unwrap_err();
+        assert!(error.to_string().contains("printed no token"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn oauth2_tokens_are_cached() {
+        let (credentials, requests) =
+            token_endpoint("200 OK", r#"{"access_token":"abc","expires_in":0}"#).await;
+
+        assert_eq!(credentials.authorization().await.unwrap(), "Bearer abc");
+        assert_eq!(credentials.authorization().await.unwrap(), "Bearer abc");
+        assert!(credentials.refresh_at().await.is_none());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn oauth2_errors_are_reported_and_backed_off() {
+        let (credentials, requests) =
+            token_endpoint("401 Unauthorized", r#"{"error":"invalid_client"}"#).await;
+
+        let error = credentials.authorization().await.unwrap_err();
+        assert!(error.is::<ProxyError>());
+        let message = error.to_string();
+        assert!(
+            message.contains("401") && message.contains("invalid_client"),
+            "{}",
+            message
+        );
+
+        let error = credentials.authorization().await.unwrap_err();
+        assert!(error.to_string().contains("retrying in 1s"), "{}", error);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,1190 @@
+//! HTTP server for the transports clients connect to the proxy with.
+//!
+//! Every connection or session gets its own MCP server built from the same
+//! `McpProxy`, so upstream clients, caches and limits are shared. SSE and
+//! WebSocket sessions last as long as their connection. Streamable HTTP
+//! sessions last until the client deletes them or stops sending requests,
+//! and number their events so a dropped stream can be resumed with
+//! `Last-Event-ID`. Sessions belong to the subject that opened them, and
+//! WebSocket sessions close once their credentials stop being valid.
+use actix_web::dev::Server;
+use actix_web::middleware::{from_fn, Logger};
+use actix_web::{web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer};
+use anyhow::{Context, Result};
+use async_mcp::transport::{JsonRpcMessage, Message, RequestId, Transport};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+use crate::auth::{authenticate, Authenticator, Identity};
+use crate::types::{DownstreamTransport, ListenAddress, ProxyServerConfig};
+use crate::McpProxy;
+
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+/// Largest request body accepted from a client
+const MAX_BODY: usize = 16 * 1024 * 1024;
+/// Events kept per Streamable HTTP session for resuming streams
+const HISTORY: usize = 256;
+
+/// Server-side transport fed by the HTTP handlers of one session
+#[derive(Clone)]
+struct SessionTransport {
+    incoming: Arc<tokio::sync::Mutex<mpsc::Receiver<Message>>>,
+    outgoing: Outgoing,
+}
+
+#[derive(Clone)]
+enum Outgoing {
+    /// A single connection, SSE or WebSocket
+    Connection(mpsc::UnboundedSender<Message>),
+    Session(Arc<HttpSession>),
+}
+
+impl SessionTransport {
+    fn new(incoming: mpsc::Receiver<Message>, outgoing: Outgoing) -> Self {
+        Self {
+            incoming: Arc::new(tokio::sync::Mutex::new(incoming)),
+            outgoing,
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for SessionTransport {
+    async fn receive(&self) -> Result<Option<Message>> {
+        Ok(self.incoming.lock().await.recv().await)
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        match &self.outgoing {
+            Outgoing::Connection(tx) => tx
+                .send(message.clone())
+                .map_err(|_| anyhow::anyhow!("Client disconnected")),
+            Outgoing::Session(session) => {
+                session.publish(message.clone());
+                Ok(())
+            }
+        }
+    }
+
+    async fn open(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Serve one session with a server built from the proxy until its transport closes
+fn serve(proxy: McpProxy, transport: SessionTransport) {
+    tokio::spawn(async move {
+        match proxy.build(transport).await {
+            Ok(server) => {
+                if let Err(e) = server.listen().await {
+                    debug!("Session ended: {:?}", e);
+                }
+            }
+            Err(e) => error!("Failed to build server: {:?}", e),
+        }
+    });
+}
+
+/// Bind the configured transports, the returned server runs when awaited or spawned
+pub fn proxy_server(config: &ProxyServerConfig, proxy: McpProxy) -> Result<Server> {
+    let mut routes = Vec::new();
+    for transport in &config.transports {
+        let route = match transport {
+            DownstreamTransport::Sse { path, message_path } => {
+                info!("SSE endpoint: {}, messages at {}", path, message_path);
+                Route::Sse(SseState {
+                    proxy: proxy.clone(),
+                    path: path.clone(),
+                    message_path: message_path.clone(),
+                    sessions: Arc::new(Mutex::new(HashMap::new())),
+                })
+            }
+            DownstreamTransport::Ws { path } => {
+                info!("WebSocket endpoint: {}", path);
+                Route::Ws {
+                    proxy: proxy.clone(),
+                    path: path.clone(),
+                }
+            }
+            DownstreamTransport::Http {
+                path,
+                session_timeout_secs,
+                allowed_origins,
+            } => {
+                info!("Streamable HTTP endpoint: {}", path);
+                let state = HttpState {
+                    proxy: proxy.clone(),
+                    path: path.clone(),
+                    sessions: Arc::new(Mutex::new(HashMap::new())),
+                    allowed_origins: Arc::new(allowed_origins.clone()),
+                };
+                tokio::spawn(expire_sessions(
+                    state.sessions.clone(),
+                    Duration::from_secs((*session_timeout_secs).max(1)),
+                ));
+                Route::Http(state)
+            }
+        };
+        routes.push(route);
+    }
+
+    let addresses = listen_addresses(config)?;
+
+    let authenticator = config
+        .auth
+        .clone()
+        .map(|auth| web::Data::new(Authenticator::new(auth)));
+
+    let mut server = HttpServer::new(move || {
+        let mut app = App::new()
+            .wrap(from_fn(authenticate))
+            .wrap(Logger::default())
+            .app_data(web::PayloadConfig::new(MAX_BODY));
+        if let Some(authenticator) = &authenticator {
+            app = app.app_data(authenticator.clone());
+        }
+        for route in &routes {
+            app = match route.clone() {
+                Route::Sse(state) => app
+                    .service(
+                        web::resource(&state.path)
+                            .app_data(web::Data::new(state.clone()))
+                            .route(web::get().to(sse_connect)),
+                    )
+                    .service(
+                        web::resource(&state.message_path)
+                            .app_data(web::Data::new(state.clone()))
+                            .route(web::post().to(sse_message)),
+                    ),
+                Route::Ws { proxy, path } => app.service(
+                    web::resource(&path)
+                        .app_data(web::Data::new(proxy))
+                        .route(web::get().to(ws_connect)),
+                ),
+                Route::Http(state) => app.service(
+                    web::resource(&state.path)
+                        .app_data(web::Data::new(state.clone()))
+                        .route(web::post().to(http_post))
+                        .route(web::get().to(http_get))
+                        .route(web::delete().to(http_delete)),
+                ),
+            };
+        }
+        app
+    });
+    for address in &addresses {
+        server = match address {
+            ListenAddress::Tcp(address) => server
+                .bind(address.as_str())
+                .with_context(|| format!("Failed to listen on {}", address))?,
+            #[cfg(unix)]
+            ListenAddress::Unix { unix, mode: None } => server
+                .bind_uds(unix)
+                .with_context(|| format!("Failed to listen on {}", unix))?,
+            #[cfg(unix)]
+            ListenAddress::Unix {
+                unix,
+                mode: Some(mode),
+            } => server
+                .listen_uds(bind_with_mode(unix, mode)?)
+                .with_context(|| format!("Failed to listen on {}", unix))?,
+            #[cfg(not(unix))]
+            ListenAddress::Unix { unix, .. } => {
+                anyhow::bail!(
+                    "Cannot listen on {}, Unix domain sockets are not supported",
+                    unix
+                )
+            }
+        };
+        info!("Listening on {}", address);
+    }
+    Ok(server.run())
+}
+
+/// The configured `listen` addresses, or all interfaces on `port` without them
+fn listen_addresses(config: &ProxyServerConfig) -> Result<Vec<ListenAddress>> {
+    match (&config.listen[..], config.port) {
+        ([], Some(port)) => Ok(vec![ListenAddress::Tcp(format!("0.0.0.0:{}", port))]),
+        ([], None) => anyhow::bail!("Set `port` or `listen` to accept clients"),
+        (addresses, _) => Ok(addresses.to_vec()),
+    }
+}
+
+/// Bind a Unix domain socket that only the given mode lets clients connect to.
+///
+/// The socket is created inside a directory only the proxy can enter and moved
+/// into place once its mode is set, so nobody can connect in between.
+#[cfg(unix)]
+fn bind_with_mode(path: &str, mode: &str) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::path::Path;
+
+    let mode = u32::from_str_radix(mode, 8)
+        .with_context(|| format!("Invalid mode {} for {}", mode, path))?;
+    let target = Path::new(path);
+    let parent = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let private = parent.join(format!(".mcp-proxy-{}", std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("Failed to create {}", private.display()))?;
+    let socket = private.join("socket");
+    let bound = std::os::unix::net::UnixListener::bind(&socket)
+        .with_context(|| format!("Failed to listen on {}", path))
+        .and_then(|listener| {
+            std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(mode))?;
+            // Replaces a stale socket left behind by a previous run
+            std::fs::rename(&socket, target)
+                .with_context(|| format!("Failed to move socket to {}", path))?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_dir(&private);
+    bound
+}
+
+#[derive(Clone)]
+enum Route {
+    Sse(SseState),
+    Ws { proxy: McpProxy, path: String },
+    Http(HttpState),
+}
+
+#[derive(Clone)]
+struct SseState {
+    proxy: McpProxy,
+    path: String,
+    message_path: String,
+    sessions: Arc<Mutex<HashMap<String, SseClient>>>,
+}
+
+/// An open SSE session and the client it belongs to
+#[derive(Clone)]
+struct SseClient {
+    incoming: mpsc::Sender<Message>,
+    identity: Identity,
+}
+
+#[derive(Deserialize)]
+struct MessageQuery {
+    #[serde(rename = "sessionId")]
+    session_id: Option<String>,
+}
+
+/// Ends an SSE session once its event stream is dropped
+struct SseSession {
+    sessions: Arc<Mutex<HashMap<String, SseClient>>>,
+    id: String,
+}
+
+impl Drop for SseSession {
+    fn drop(&mut self) {
+        debug!("SSE session {} disconnected", self.id);
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.remove(&self.id);
+        }
+    }
+}
+
+fn sse_event(id: Option<&str>, message: &Message) -> web::Bytes {
+    let data = serde_json::to_string(message).unwrap_or_default();
+    let event = match id {
+        Some(id) => format!("id: {}\nevent: message\ndata: {}\n\n", id, data),
+        None => format!("event: message\ndata: {}\n\n", data),
+    };
+    web::Bytes::from(event)
+}
+
+/// The identity the request authenticated as, anonymous without authentication
+fn identity(req: &HttpRequest) -> Identity {
+    req.extensions()
+        .get::<Identity>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+async fn sse_connect(req: HttpRequest, state: web::Data<SseState>) -> HttpResponse {
+    let session_id = Uuid::new_v4().to_string();
+    let identity = identity(&req);
+    let (incoming_tx, incoming_rx) = mpsc::channel(100);
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+    if let Ok(mut sessions) = state.sessions.lock() {
+        sessions.insert(
+            session_id.clone(),
+            SseClient {
+                incoming: incoming_tx,
+                identity: identity.clone(),
+            },
+        );
+    }
+    serve(
+        state.proxy.clone().with_identity(identity),
+        SessionTransport::new(incoming_rx, Outgoing::Connection(outgoing_tx)),
+    );
+    info!(
+        "SSE session {} opened from {}",
+        session_id,
+        req.peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    );
+
+    let info = req.connection_info();
+    let endpoint = format!(
+        "event: endpoint\ndata: {}://{}{}?sessionId={}\n\n",
+        info.scheme(),
+        info.host(),
+        state.message_path,
+        session_id
+    );
+    let session = SseSession {
+        sessions: state.sessions.clone(),
+        id: session_id.clone(),
+    };
+    let events = futures::stream::unfold((outgoing_rx, session), |(mut rx, session)| async move {
+        let message = rx.recv().await?;
+        Some((
+            Ok::<_, std::convert::Infallible>(sse_event(None, &message)),
+            (rx, session),
+        ))
+    });
+    let stream = futures::stream::once(async move {
+        Ok::<_, std::convert::Infallible>(web::Bytes::from(endpoint))
+    })
+    .chain(events);
+
+    HttpResponse::Ok()
+        .append_header(("X-Session-Id", session_id))
+        .content_type("text/event-stream")
+        .streaming(stream)
+}
+
+async fn sse_message(
+    req: HttpRequest,
+    query: web::Query<MessageQuery>,
+    body: web::Bytes,
+    state: web::Data<SseState>,
+) -> HttpResponse {
+    let Some(session_id) = &query.session_id else {
+        return HttpResponse::BadRequest().body("Session ID not specified");
+    };
+    let Some(session) = state
+        .sessions
+        .lock()
+        .ok()
+        .and_then(|sessions| sessions.get(session_id).cloned())
+        .filter(|session| session.identity.subject == identity(&req).subject)
+    else {
+        return HttpResponse::NotFound().body(format!("Session {} not found", session_id));
+    };
+    let message = match serde_json::from_slice::<Message>(&body) {
+        Ok(message) => message,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid message: {}", e)),
+    };
+    match session.incoming.send(message).await {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(_) => HttpResponse::NotFound().body(format!("Session {} not found", session_id)),
+    }
+}
+
+async fn ws_connect(
+    req: HttpRequest,
+    body: web::Payload,
+    proxy: web::Data<McpProxy>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    // Keys can be revoked and tokens expire while the socket stays open
+    let credentials = req.app_data::<web::Data<Authenticator>>().cloned().zip(
+        req.headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    );
+    let (incoming_tx, incoming_rx) = mpsc::channel(100);
+    let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+    serve(
+        proxy.get_ref().clone().with_identity(identity(&req)),
+        SessionTransport::new(incoming_rx, Outgoing::Connection(outgoing_tx)),
+    );
+    info!(
+        "WebSocket session opened from {}",
+        req.peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    );
+
+    actix_web::rt::spawn(async move {
+        let mut reason = None;
+        loop {
+            tokio::select! {
+                message = messages.next() => match message {
+                    Some(Ok(actix_ws::Message::Text(text))) => {
+                        if let Some((authenticator, header)) = &credentials {
+                            if authenticator.verify(Some(header)).is_err() {
+                                info!("Closing WebSocket session, its credentials are no longer valid");
+                                reason = Some(actix_ws::CloseReason {
+                                    code: actix_ws::CloseCode::Policy,
+                                    description: Some("Invalid or expired token".to_string()),
+                                });
+                                break;
+                            }
+                        }
+                        match serde_json::from_str::<Message>(&text) {
+                            Ok(message) => {
+                                if incoming_tx.send(message).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) => debug!("Ignoring invalid WebSocket message: {}", e),
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+                message = outgoing_rx.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    let Ok(text) = serde_json::to_string(&message) else {
+                        continue;
+                    };
+                    if session.text(text).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        debug!("WebSocket session closed");
+        let _ = session.close(reason).await;
+    });
+
+    Ok(response)
+}
+
+#[derive(Clone)]
+struct HttpState {
+    proxy: McpProxy,
+    path: String,
+    sessions: Arc<Mutex<HashMap<String, Arc<HttpSession>>>>,
+    allowed_origins: Arc<Vec<String>>,
+}
+
+#[derive(Clone)]
+struct Event {
+    id: u64,
+    stream: u64,
+    message: Message,
+}
+
+impl Event {
+    /// Event ids name their stream so `Last-Event-ID` identifies what to resume
+    fn id(&self) -> String {
+        format!("{}/{}", self.stream, self.id)
+    }
+}
+
+/// The standalone stream a client opens with GET
+const STANDALONE: u64 = 0;
+
+#[derive(Default)]
+struct Streams {
+    next_event: u64,
+    next_stream: u64,
+    /// The stream the response to each open request goes to
+    routes: HashMap<RequestId, u64>,
+    /// Responses a POST stream still owes, it ends when none are left
+    owed: HashMap<u64, usize>,
+    /// Connected clients by stream
+    live: HashMap<u64, mpsc::UnboundedSender<Event>>,
+    history: VecDeque<Event>,
+}
+
+/// A Streamable HTTP session and the streams of its requests
+struct HttpSession {
+    /// Taken when the session ends, which ends its server
+    incoming: Mutex<Option<mpsc::Sender<Message>>>,
+    streams: Mutex<Streams>,
+    last_seen: Mutex<Instant>,
+    /// Only the client that opened the session may use it
+    identity: Identity,
+}
+
+impl HttpSession {
+    fn touch(&self) {
+        if let Ok(mut last_seen) = self.last_seen.lock() {
+            *last_seen = Instant::now();
+        }
+    }
+
+    fn close(&self) {
+        if let Ok(mut incoming) = self.incoming.lock() {
+            incoming.take();
+        }
+    }
+
+    fn expired(&self, timeout: Duration) -> bool {
+        let idle = self
+            .last_seen
+            .lock()
+            .map(|last_seen| last_seen.elapsed() >= timeout)
+            .unwrap_or(true);
+        idle && self
+            .streams
+            .lock()
+            .is_ok_and(|streams| streams.live.is_empty())
+    }
+
+    async fn forward(&self, messages: Vec<Message>) -> Result<()> {
+        let Some(incoming) = self.incoming.lock().ok().and_then(|tx| tx.clone()) else {
+            anyhow::bail!("Session closed");
+        };
+        for message in messages {
+            incoming.send(message).await?;
+        }
+        Ok(())
+    }
+
+    /// Open a stream for the responses to `requests`
+    fn open_stream(&self, requests: &[RequestId]) -> mpsc::UnboundedReceiver<Event> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let Ok(mut streams) = self.streams.lock() else {
+            return rx;
+        };
+        streams.next_stream += 1;
+        let stream = streams.next_stream;
+        for id in requests {
+            streams.routes.insert(*id, stream);
+        }
+        streams.owed.insert(stream, requests.len());
+        streams.live.insert(stream, tx);
+        rx
+    }
+
+    /// Reconnect to a stream, replaying the events after `last_event_id`.
+    ///
+    /// Without an id this opens the standalone stream for messages that do
+    /// not answer a request.
+    fn resume(&self, last_event_id: Option<&str>) -> mpsc::UnboundedReceiver<Event> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let Ok(mut streams) = self.streams.lock() else {
+            return rx;
+        };
+        let (stream, after) = last_event_id
+            .and_then(|id| id.split_once('/'))
+            .and_then(|(stream, event)| Some((stream.parse().ok()?, event.parse().ok()?)))
+            .unwrap_or((STANDALONE, u64::MAX));
+        for event in &streams.history {
+            if event.stream == stream && event.id > after {
+                let _ = tx.send(event.clone());
+            }
+        }
+        if stream == STANDALONE || streams.owed.contains_key(&stream) {
+            streams.live.insert(stream, tx);
+        }
+        rx
+    }
+
+    /// Route a message from the server to the stream that waits for it
+    fn publish(&self, message: Message) {
+        let Ok(mut streams) = self.streams.lock() else {
+            return;
+        };
+        let stream = match &message {
+            JsonRpcMessage::Response(response) => {
+                streams.routes.remove(&response.id).unwrap_or(STANDALONE)
+            }
+            _ => STANDALONE,
+        };
+        streams.next_event += 1;
+        let event = Event {
+            id: streams.next_event,
+            stream,
+            message,
+        };
+        streams.history.push_back(event.clone());
+        if streams.history.len() > HISTORY {
+            streams.history.pop_front();
+        }
+        if let Some(tx) = streams.live.get(&stream) {
+            if tx.send(event).is_err() {
+                streams.live.remove(&stream);
+            }
+        }
+        if stream == STANDALONE {
+            return;
+        }
+        if let Some(owed) = streams.owed.get_mut(&stream) {
+            *owed = owed.saturating_sub(1);
+            if *owed == 0 {
+                streams.owed.remove(&stream);
+                streams.live.remove(&stream);
+            }
+        }
+    }
+}
+
+/// Drop sessions that saw no request for `timeout` and have no open stream
+async fn expire_sessions(
+    sessions: Arc<Mutex<HashMap<String, Arc<HttpSession>>>>,
+    timeout: Duration,
+) {
+    let mut interval = tokio::time::interval((timeout / 2).min(Duration::from_secs(60)));
+    loop {
+        interval.tick().await;
+        let Ok(mut sessions) = sessions.lock() else {
+            continue;
+        };
+        sessions.retain(|id, session| {
+            if !session.expired(timeout) {
+                return true;
+            }
+            info!("Streamable HTTP session {} expired", id);
+            session.close();
+            false
+        });
+    }
+}
+
+fn session_header(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(SESSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+fn accepts_events(req: &HttpRequest) -> bool {
+    req.headers()
+        .get("Accept")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/event-stream"))
+}
+
+fn jsonrpc_error(code: i32, message: &str) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Whether a browser origin is in the allowlist, either exactly or by an
+/// entry without a port
+fn origin_allowed(origin: &str, allowed: &[String]) -> bool {
+    let without_port = url::Url::parse(origin)
+        .ok()
+        .filter(|url| url.port().is_some())
+        .and_then(|url| Some(format!("{}://{}", url.scheme(), url.host_str()?)));
+    allowed.iter().any(|allowed| {
+        allowed == "*" || allowed == origin || without_port.as_ref() == Some(allowed)
+    })
+}
+
+/// Refuse requests from web pages of other origins, which could otherwise
+/// reach a proxy listening on localhost through the user's browser. Clients
+/// other than browsers send no `Origin`.
+fn check_origin(req: &HttpRequest, state: &HttpState) -> std::result::Result<(), HttpResponse> {
+    let Some(origin) = req.headers().get("Origin") else {
+        return Ok(());
+    };
+    match origin.to_str() {
+        Ok(origin) if origin_allowed(origin, &state.allowed_origins) => Ok(()),
+        _ => {
+            info!("Refused Streamable HTTP request from origin {:?}", origin);
+            Err(HttpResponse::Forbidden().json(jsonrpc_error(-32000, "Origin not allowed")))
+        }
+    }
+}
+
+/// Look up the session a request names, or the error response to send
+fn find_session(
+    req: &HttpRequest,
+    state: &HttpState,
+) -> std::result::Result<(String, Arc<HttpSession>), HttpResponse> {
+    let Some(session_id) = session_header(req) else {
+        return Err(
+            HttpResponse::BadRequest().json(jsonrpc_error(-32000, "Missing Mcp-Session-Id header"))
+        );
+    };
+    let session = state
+        .sessions
+        .lock()
+        .ok()
+        .and_then(|sessions| sessions.get(&session_id).cloned())
+        .filter(|session| session.identity.subject == identity(req).subject);
+    match session {
+        Some(session) => {
+            session.touch();
+            Ok((session_id, session))
+        }
+        None => Err(HttpResponse::NotFound().json(jsonrpc_error(-32001, "Session not found"))),
+    }
+}
+
+fn event_stream(session_id: String, rx: mpsc::UnboundedReceiver<Event>) -> HttpResponse {
+    let events = futures::stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        Some((
+            Ok::<_, std::convert::Infallible>(sse_event(Some(&event.id()), &event.message)),
+            rx,
+        ))
+    });
+    HttpResponse::Ok()
+        .append_header((SESSION_HEADER, session_id))
+        .content_type("text/event-stream")
+        .streaming(events)
+}
+
+async fn http_post(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<HttpState>,
+) -> HttpResponse {
+    if let Err(response) = check_origin(&req, &state) {
+        return response;
+    }
+    let (messages, batch) = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(serde_json::Value::Array(values)) => (
+            values
+                .into_iter()
+                .map(serde_json::from_value::<Message>)
+                .collect::<std::result::Result<Vec<_>, _>>(),
+            true,
+        ),
+        Ok(value) => (
+            serde_json::from_value::<Message>(value).map(|m| vec![m]),
+            false,
+        ),
+        Err(e) => (Err(e), false),
+    };
+    let messages = match messages {
+        Ok(messages) if !messages.is_empty() => messages,
+        Ok(_) => return HttpResponse::BadRequest().json(jsonrpc_error(-32600, "Empty batch")),
+        Err(e) => {
+            return HttpResponse::BadRequest()
+                .json(jsonrpc_error(-32700, &format!("Parse error: {}", e)))
+        }
+    };
+
+    let initialize = messages.iter().any(
+        |message| matches!(message, JsonRpcMessage::Request(request) if request.method == "initialize"),
+    );
+    let (session_id, session) = if initialize {
+        let session_id = Uuid::new_v4().to_string();
+        let identity = identity(&req);
+        let (incoming_tx, incoming_rx) = mpsc::channel(100);
+        let session = Arc::new(HttpSession {
+            incoming: Mutex::new(Some(incoming_tx)),
+            streams: Mutex::new(Streams::default()),
+            last_seen: Mutex::new(Instant::now()),
+            identity: identity.clone(),
+        });
+        serve(
+            state.proxy.clone().with_identity(identity),
+            SessionTransport::new(incoming_rx, Outgoing::Session(session.clone())),
+        );
+        if let Ok(mut sessions) = state.sessions.lock() {
+            sessions.insert(session_id.clone(), session.clone());
+        }
+        info!("Streamable HTTP session {} opened", session_id);
+        (session_id, session)
+    } else {
+        match find_session(&req, &state) {
+            Ok(found) => found,
+            Err(response) => return response,
+        }
+    };
+
+    let requests: Vec<RequestId> = messages
+        .iter()
+        .filter_map(|message| match message {
+            JsonRpcMessage::Request(request) => Some(request.id),
+            _ => None,
+        })
+        .collect();
+    if requests.is_empty() {
+        return match session.forward(messages).await {
+            Ok(()) => HttpResponse::Accepted()
+                .append_header((SESSION_HEADER, session_id))
+                .finish(),
+            Err(_) => HttpResponse::NotFound().json(jsonrpc_error(-32001, "Session not found")),
+        };
+    }
+
+    // The stream is registered first so no response can arrive before it
+    let mut rx = session.open_stream(&requests);
+    if session.forward(messages).await.is_err() {
+        return HttpResponse::NotFound().json(jsonrpc_error(-32001, "Session not found"));
+    }
+    if accepts_events(&req) {
+        return event_stream(session_id, rx);
+    }
+
+    let mut responses = Vec::new();
+    while let Some(event) = rx.recv().await {
+        responses.push(event.message);
+    }
+    let body = match (batch, responses.len()) {
+        (false, 1) => serde_json::to_value(&responses[0]),
+        _ => serde_json::to_value(&responses),
+    };
+    HttpResponse::Ok()
+        .append_header((SESSION_HEADER, session_id))
+        .json(body.unwrap_or_default())
+}
+
+async fn http_get(req: HttpRequest, state: web::Data<HttpState>) -> HttpResponse {
+    if let Err(response) = check_origin(&req, &state) {
+        return response;
+    }
+    if !accepts_events(&req) {
+        return HttpResponse::MethodNotAllowed().finish();
+    }
+    let (session_id, session) = match find_session(&req, &state) {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok());
+    event_stream(session_id, session.resume(last_event_id))
+}
+
+async fn http_delete(req: HttpRequest, state: web::Data<HttpState>) -> HttpResponse {
+    if let Err(response) = check_origin(&req, &state) {
+        return response;
+    }
+    let (session_id, session) = match find_session(&req, &state) {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    if let Ok(mut sessions) = state.sessions.lock() {
+        sessions.remove(&session_id);
+    }
+    session.close();
+    info!("Streamable HTTP session {} ended by the client", session_id);
+    HttpResponse::Ok().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_mcp::transport::{JsonRpcNotification, JsonRpcResponse};
+
+    fn session() -> HttpSession {
+        HttpSession {
+            incoming: Mutex::new(None),
+            streams: Mutex::new(Streams::default()),
+            last_seen: Mutex::new(Instant::now()),
+            identity: Identity::default(),
+        }
+    }
+
+    fn response(id: RequestId) -> Message {
+        JsonRpcMessage::Response(JsonRpcResponse {
+            id,
+            result: Some(json!({})),
+            ..Default::default()
+        })
+    }
+
+    fn notification() -> Message {
+        JsonRpcMessage::Notification(JsonRpcNotification {
+            method: "notifications/progress".to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// Event ids received until the stream ends or goes quiet
+    fn received(rx: &mut mpsc::UnboundedReceiver<Event>) -> Vec<String> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|event| event.id())
+            .collect()
+    }
+
+    fn ended(rx: &mut mpsc::UnboundedReceiver<Event>) -> bool {
+        matches!(rx.try_recv(), Err(mpsc::error::TryRecvError::Disconnected))
+    }
+
+    #[test]
+    fn responses_go_to_the_stream_of_their_request() {
+        let session = session();
+        let mut first = session.open_stream(&[1]);
+        let mut second = session.open_stream(&[2, 3]);
+        session.publish(response(3));
+        session.publish(response(1));
+        assert_eq!(received(&mut first), vec!["1/2"]);
+        assert_eq!(received(&mut second), vec!["2/1"]);
+
+        // A stream ends once every response it owes was sent
+        assert!(ended(&mut first));
+        session.publish(response(2));
+        assert_eq!(received(&mut second), vec!["2/3"]);
+        assert!(ended(&mut second));
+    }
+
+    #[test]
+    fn resuming_replays_only_later_events_of_the_stream() {
+        let session = session();
+        let mut stream = session.open_stream(&[1, 2]);
+        session.publish(notification());
+        session.publish(response(1));
+        assert_eq!(received(&mut stream), vec!["1/2"]);
+        drop(stream);
+
+        session.publish(response(2));
+        let mut resumed = session.resume(Some("1/2"));
+        assert_eq!(received(&mut resumed), vec!["1/3"]);
+        let mut replayed = session.resume(Some("1/0"));
+        assert_eq!(received(&mut replayed), vec!["1/2", "1/3"]);
+    }
+
+    #[test]
+    fn resumed_streams_receive_the_responses_still_owed() {
+        let session = session();
+        drop(session.open_stream(&[1, 2]));
+        session.publish(response(1));
+
+        let mut resumed = session.resume(Some("1/1"));
+        assert!(received(&mut resumed).is_empty());
+        session.publish(response(2));
+        assert_eq!(received(&mut resumed), vec!["1/2"]);
+        assert!(ended(&mut resumed));
+    }
+
+    #[test]
+    fn without_an_event_id_the_standalone_stream_opens() {
+        let session = session();
+        let mut standalone = session.resume(None);
+        let _stream = session.open_stream(&[1]);
+        session.publish(notification());
+        session.publish(response(1));
+        session.publish(response(9));
+        assert_eq!(received(&mut standalone), vec!["0/1", "0/3"]);
+
+        let mut malformed = session.resume(Some("garbage"));
+        session.publish(notification());
+        assert_eq!(received(&mut malformed), vec!["0/4"]);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let session = session();
+        for _ in 0..HISTORY + 10 {
+            session.publish(notification());
+        }
+        let mut replayed = session.resume(Some("0/0"));
+        let ids = received(&mut replayed);
+        assert_eq!(ids.len(), HISTORY);
+        assert_eq!(ids[0], "0/11");
+    }
+
+    #[test]
+    fn sessions_with_open_streams_do_not_expire() {
+        let session = session();
+        let stream = session.open_stream(&[1]);
+        assert!(!session.expired(Duration::ZERO));
+        drop(stream);
+        session.publish(response(1));
+        assert!(session.expired(Duration::ZERO));
+        assert!(!session.expired(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn origins_match_exactly_or_by_host() {
+        let allowed = crate::types::default_allowed_origins();
+        assert!(origin_allowed("http://localhost", &allowed));
+        assert!(origin_allowed("http://localhost:5173", &allowed));
+        assert!(origin_allowed("https://127.0.0.1:8443", &allowed));
+        assert!(origin_allowed("http://[::1]:3000", &allowed));
+        assert!(!origin_allowed("https://evil.example", &allowed));
+        assert!(!origin_allowed("http://localhost.evil.example", &allowed));
+        assert!(!origin_allowed("null", &allowed));
+
+        let allowed = vec!["https://app.example.com:8443".to_string()];
+        assert!(origin_allowed("https://app.example.com:8443", &allowed));
+        assert!(!origin_allowed("https://app.example.com:9443", &allowed));
+        assert!(!origin_allowed("http://localhost", &allowed));
+        assert!(origin_allowed("https://anything", &["*".to_string()]));
+    }
+
+    #[actix_web::test]
+    async fn requests_from_other_origins_are_forbidden() {
+        use actix_web::test::{call_service, init_service, TestRequest};
+
+        let config: ProxyServerConfig = serde_json::from_value(json!({ "servers": {} })).unwrap();
+        let proxy = McpProxy::new(Arc::new(config), r#"{"tools":{},"resources":{}}"#).unwrap();
+        let state = HttpState {
+            proxy,
+            path: "/mcp".to_string(),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            allowed_origins: Arc::new(crate::types::default_allowed_origins()),
+        };
+        let app = init_service(
+            App::new().service(
+                web::resource("/mcp")
+                    .app_data(web::Data::new(state))
+                    .route(web::post().to(http_post))
+                    .route(web::get().to(http_get))
+                    .route(web::delete().to(http_delete)),
+            ),
+        )
+        .await;
+        let status = |request: TestRequest, origin: Option<&str>| {
+            let request = match origin {
+                Some(origin) => request.insert_header(("Origin", origin)),
+                None => request,
+            };
+            let app = &app;
+            async move { call_service(app, request.to_request()).await.status() }
+        };
+        let post = || TestRequest::post().uri("/mcp").set_payload("[]");
+
+        assert_eq!(status(post(), Some("https://evil.example")).await, 403);
+        assert_eq!(
+            status(TestRequest::get().uri("/mcp"), Some("https://evil.example")).await,
+            403
+        );
+        assert_eq!(
+            status(
+                TestRequest::delete().uri("/mcp"),
+                Some("https://evil.example")
+            )
+            .await,
+            403
+        );
+        // Past the check, the empty batch is refused
+        assert_eq!(status(post(), Some("http://localhost:5173")).await, 400);
+        assert_eq!(status(post(), None).await, 400);
+    }
+
+    fn listen_config(listen: serde_json::Value) -> ProxyServerConfig {
+        let mut config = json!({ "servers": {}, "port": 3004 });
+        config["listen"] = listen;
+        serde_json::from_value(config).unwrap()
+    }
+
+    /// A directory removed with everything in it when dropped
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("mcp-proxy-{}", Uuid::new_v4()));
+            std::fs::create_dir(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.0.join(file).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn listen_addresses_are_tcp_or_unix_sockets() {
+        let config = listen_config(json!([
+            "127.0.0.1:3004",
+            "[::1]:3004",
+            { "unix": "/run/proxy.sock" },
+            { "unix": "/run/private.sock", "mode": "600" },
+        ]));
+        let addresses: Vec<String> = listen_addresses(&config)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                "http://127.0.0.1:3004",
+                "http://[::1]:3004",
+                "unix:/run/proxy.sock",
+                "unix:/run/private.sock",
+            ]
+        );
+        assert!(matches!(
+            &config.listen[3],
+            ListenAddress::Unix { mode: Some(mode), .. } if mode == "600"
+        ));
+    }
+
+    #[test]
+    fn port_is_used_without_listen_addresses() {
+        let addresses = listen_addresses(&listen_config(json!([]))).unwrap();
+        assert_eq!(addresses[0].to_string(), "http://0.0.0.0:3004");
+        assert_eq!(addresses.len(), 1);
+
+        let config: ProxyServerConfig = serde_json::from_value(json!({ "servers": {} })).unwrap();
+        assert!(listen_addresses(&config).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sockets_get_their_mode_and_replace_stale_files() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        let dir = TempDir::new();
+        let path = dir.path("proxy.sock");
+        std::fs::write(&path, "stale").unwrap();
+
+        let _listener = bind_with_mode(&path, "640").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        // The private directory it was created in is gone
+        let entries: Vec<_> = std::fs::read_dir(&dir.0).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+
+        assert!(bind_with_mode(&dir.path("other.sock"), "rw").is_err());
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn the_server_listens_on_unix_sockets() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        let dir = TempDir::new();
+        let (plain, private) = (dir.path("plain.sock"), dir.path("private.sock"));
+        std::fs::write(&plain, "stale").unwrap();
+        let config = listen_config(json!([{ "unix": plain }, { "unix": private, "mode": "600" }]));
+        let proxy =
+            McpProxy::new(Arc::new(config.clone()), r#"{"tools":{},"resources":{}}"#).unwrap();
+
+        let server = proxy_server(&config, proxy).unwrap();
+        let handle = server.handle();
+        tokio::spawn(server);
+        for path in [&plain, &private] {
+            assert!(std::fs::metadata(path).unwrap().file_type().is_socket());
+            tokio::net::UnixStream::connect(path).await.unwrap();
+        }
+        let mode = std::fs::metadata(&private).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        handle.stop(false).await;
+    }
+}
@@ -0,0 +1,393 @@
+//! Streamable HTTP transport to an upstream server.
+//!
+//! Every message is POSTed to a single endpoint. The server answers requests
+//! with either a JSON body or an SSE stream that carries the response, and
+//! may hand out an `Mcp-Session-Id` during the initialize handshake that is
+//! echoed on every later request. Server-initiated streams opened with GET
+//! are not used.
+use anyhow::Result;
+use async_mcp::transport::{JsonRpcNotification, JsonRpcRequest, Message, Transport};
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::header::{HeaderMap, ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, warn};
+
+use crate::credentials::Credentials;
+
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+/// First protocol revision with Streamable HTTP
+const PROTOCOL_VERSION: &str = "2025-03-26";
+/// Request id of the initialize handshake, far above the ids the client uses
+/// but still exact in servers that parse ids as doubles
+const HANDSHAKE_ID: u64 = (1 << 53) - 1;
+
+/// A message the server answered with an error status. Only the request it
+/// carried fails, the session stays usable.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Request failed with status {}: {}",
+            self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+struct Endpoint {
+    url: String,
+    headers: HashMap<String, String>,
+    credentials: Option<Arc<Credentials>>,
+}
+
+#[derive(Clone)]
+pub struct StreamableHttpTransport {
+    endpoint: Arc<Endpoint>,
+    http: reqwest::Client,
+    session_id: Arc<std::sync::Mutex<Option<String>>>,
+    tx: mpsc::Sender<Message>,
+    rx: Arc<Mutex<mpsc::Receiver<Message>>>,
+}
+
+impl StreamableHttpTransport {
+    pub fn new(
+        url: &str,
+        headers: Option<HashMap<String, String>>,
+        credentials: Option<Arc<Credentials>>,
+        connect_timeout: Duration,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(100);
+        let http = reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .build()
+            .unwrap_or_default();
+        Self {
+            endpoint: Arc::new(Endpoint {
+                url: url.to_string(),
+                headers: headers.unwrap_or_default(),
+                credentials,
+            }),
+            http,
+            session_id: Arc::new(std::sync::Mutex::new(None)),
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+        }
+    }
+
+    fn session_id(&self) -> Option<String> {
+        self.session_id.lock().ok().and_then(|id| id.clone())
+    }
+
+    /// Add the configured headers, credentials and session id. Tokens are
+    /// looked up for every request, so a refreshed token is used right away.
+    async fn with_headers(&self, mut request: RequestBuilder) -> Result<RequestBuilder> {
+        for (key, value) in &self.endpoint.headers {
+            request = request.header(key, value);
+        }
+        if let Some(credentials) = &self.endpoint.credentials {
+            request = request.header(AUTHORIZATION, credentials.authorization().await?);
+        }
+        Ok(match self.session_id() {
+            Some(session_id) => request.header(SESSION_HEADER, session_id),
+            None => request,
+        })
+    }
+
+    /// POST a message, returning the response when it has a body to read
+    async fn post(&self, message: &Message) -> Result<Option<Response>> {
+        let request = self
+            .http
+            .post(&self.endpoint.url)
+            .header(ACCEPT, "application/json, text/event-stream")
+            .json(message);
+        let response = self.with_headers(request).await?.send().await?;
+        self.store_session_id(response.headers());
+
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND && self.session_id().is_some() {
+            if let Ok(mut session_id) = self.session_id.lock() {
+                *session_id = None;
+            }
+            anyhow::bail!("Streamable HTTP session at {} expired", self.endpoint.url);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+        if status == StatusCode::ACCEPTED {
+            return Ok(None);
+        }
+        Ok(Some(response))
+    }
+
+    fn store_session_id(&self, headers: &HeaderMap) {
+        let Some(session_id) = headers
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return;
+        };
+        if let Ok(mut current) = self.session_id.lock() {
+            if current.as_deref() != Some(session_id) {
+                debug!(
+                    "Streamable HTTP session {} at {}",
+                    session_id, self.endpoint.url
+                );
+                *current = Some(session_id.to_string());
+            }
+        }
+    }
+}
+
+/// Forward the messages of a response body, which is JSON or an SSE stream
+async fn forward(response: Response, tx: &mpsc::Sender<Message>) -> Result<()> {
+    let streamed = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    if streamed {
+        forward_events(response, tx).await
+    } else {
+        forward_json(response, tx).await
+    }
+}
+
+/// Forward the messages of a JSON body, which holds one message or a batch
+async fn forward_json(response: Response, tx: &mpsc::Sender<Message>) -> Result<()> {
+    let body = response.bytes().await?;
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(());
+    }
+    let messages = match serde_json::from_slice::<Vec<Message>>(&body) {
+        Ok(messages) => messages,
+        Err(_) => vec![serde_json::from_slice::<Message>(&body)?],
+    };
+    for message in messages {
+        tx.send(message).await?;
+    }
+    Ok(())
+}
+
+/// Forward the `data` of every event in an SSE body until the server ends it
+async fn forward_events(response: Response, tx: &mpsc::Sender<Message>) -> Result<()> {
+    let mut stream = response.bytes_stream();
+    let mut buffer = String::new();
+    while let Some(chunk) = stream.next().await {
+        buffer.push_str(&String::from_utf8_lossy(&chunk?).replace("\r\n", "\n"));
+        while let Some(end) = buffer.find("\n\n") {
+            let event: String = buffer.drain(..end + 2).collect();
+            let data = event
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if data.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Message>(&data) {
+                Ok(message) => tx.send(message).await?,
+                Err(e) => debug!("Skipping unparsable SSE event: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Transport for StreamableHttpTransport {
+    async fn receive(&self) -> Result<Option<Message>> {
+        let mut rx = self.rx.lock().await;
+        Ok(rx.recv().await)
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        let Some(response) = self.post(message).await? else {
+            return Ok(());
+        };
+        // The body is read in the background so the response is awaited with
+        // the client's request timeout
+        let tx = self.tx.clone();
+        let url = self.endpoint.url.clone();
+        tokio::spawn(async move {
+            if let Err(e) = forward(response, &tx).await {
+                warn!("Failed to read response from {}: {}", url, e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Start a session with the initialize handshake, which Streamable HTTP
+    /// servers require before any other request
+    async fn open(&self) -> Result<()> {
+        let initialize = Message::Request(JsonRpcRequest {
+            id: HANDSHAKE_ID,
+            method: "initialize".to_string(),
+            params: Some(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            })),
+            ..Default::default()
+        });
+        let response = self.post(&initialize).await?.ok_or_else(|| {
+            anyhow::anyhow!("No response to initialize from {}", self.endpoint.url)
+        })?;
+
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move { forward(response, &tx).await });
+        loop {
+            match rx.recv().await {
+                Some(Message::Response(response)) if response.id == HANDSHAKE_ID => {
+                    if let Some(error) = response.error {
+                        anyhow::bail!(
+                            "Failed to initialize {}: {}",
+                            self.endpoint.url,
+                            error.message
+                        );
+                    }
+                    break;
+                }
+                Some(_) => continue,
+                None => anyhow::bail!("No response to initialize from {}", self.endpoint.url),
+            }
+        }
+
+        let initialized = Message::Notification(JsonRpcNotification {
+            method: "notifications/initialized".to_string(),
+            ..Default::default()
+        });
+        self.post(&initialized).await?;
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        let Some(session_id) = self.session_id.lock().ok().and_then(|mut id| id.take()) else {
+            return Ok(());
+        };
+        let request = self
+            .with_headers(self.http.delete(&self.endpoint.url))
+            .await?
+            .header(SESSION_HEADER, session_id);
+        match request.send().await {
+            // Servers that do not let clients end sessions answer 405
+            Ok(response)
+                if response.status().is_success()
+                    || response.status() == StatusCode::METHOD_NOT_ALLOWED => {}
+            Ok(response) => debug!(
+                "Ending session at {} failed with {}",
+                self.endpoint.url,
+                response.status()
+            ),
+            Err(e) => debug!("Ending session at {} failed: {}", self.endpoint.url, e),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_mcp::transport::JsonRpcMessage;
+
+    /// A response whose body arrives in the given chunks
+    fn response(content_type: &str, chunks: &[&'static str]) -> Response {
+        let chunks: Vec<std::io::Result<&'static str>> = chunks.iter().map(|c| Ok(*c)).collect();
+        let body = reqwest::Body::wrap_stream(futures::stream::iter(chunks));
+        http::Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(body)
+            .unwrap()
+            .into()
+    }
+
+    async fn forwarded(response: Response) -> Vec<u64> {
+        let (tx, mut rx) = mpsc::channel(16);
+        forward(response, &tx).await.unwrap();
+        drop(tx);
+        let mut ids = Vec::new();
+        while let Some(message) = rx.recv().await {
+            match message {
+                JsonRpcMessage::Response(response) => ids.push(response.id),
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn events_split_across_chunks_are_reassembled() {
+        let response = response(
+            "text/event-stream",
+            &[
+                "event: message\ndata: {\"jsonrpc\":\"2.0\",",
+                "\"id\":1,\"result\":{}}\n",
+                "\ndata: {\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{}}\n\n",
+            ],
+        );
+        assert_eq!(forwarded(response).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn crlf_and_multi_line_data_are_supported() {
+        let response = response(
+            "text/event-stream; charset=utf-8",
+            &["data: {\"jsonrpc\":\"2.0\",\r\ndata:\"id\":3,\"result\":{}}\r\n\r\n"],
+        );
+        assert_eq!(forwarded(response).await, vec![3]);
+    }
+
+    #[tokio::test]
+    async fn events_without_a_message_are_skipped() {
+        let response = response(
+            "text/event-stream",
+            &[
+                ": keepalive\n\n",
+                "id: 7\nevent: ping\n\n",
+                "data: not json\n\n",
+                "data: {\"jsonrpc\":\"2.0\",\"id\":4,\"result\":{}}\n\n",
+                "data: {\"jsonrpc\":\"2.0\",\"id\":5",
+            ],
+        );
+        assert_eq!(forwarded(response).await, vec![4]);
+    }
+
+    #[tokio::test]
+    async fn json_bodies_hold_one_message_or_a_batch() {
+        let single = response(
+            "application/json",
+            &["{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{}}"],
+        );
+        assert_eq!(forwarded(single).await, vec![1]);
+
+        let batch = response(
+            "application/json",
+            &[
+                "[{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{}},",
+                "{\"jsonrpc\":\"2.0\",\"id\":3,\"result\":{}}]",
+            ],
+        );
+        assert_eq!(forwarded(batch).await, vec![2, 3]);
+        assert!(forwarded(response("application/json", &[" \n"]))
+            .await
+            .is_empty());
+    }
+}
@@ -0,0 +1,365 @@
+//! Named API keys kept in a YAML keys file.
+//!
+//! Only the SHA-256 of each secret is stored. The proxy rereads the file
+//! whenever it changes, so keys created or revoked with `mcp-proxy keys` take
+//! effect without a restart.
+use anyhow::{Context, Result};
+use rand::RngCore;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+
+use crate::types::RateLimit;
+
+/// Prefix of generated secrets, making them easy to spot in logs and configs
+const SECRET_PREFIX: &str = "mcpk_";
+
+/// What a key may use, checked on top of the authorization rules
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyScope {
+    /// Server name globs, all servers when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<String>,
+    /// Tool name globs, all tools when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+    /// Overrides `rate_limits.per_identity` for this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    /// Hex encoded SHA-256 of the secret
+    pub hash: String,
+    #[serde(flatten)]
+    pub scope: KeyScope,
+    /// Unix time the key was created at
+    pub created_at: u64,
+    /// Unix time after which the key is rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeysFile {
+    #[serde(default)]
+    pub keys: Vec<ApiKey>,
+}
+
+impl KeysFile {
+    /// Read a keys file, which is empty when it does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_yaml::from_str(&content)
+                .with_context(|| format!("Invalid keys file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Write the file through a rename, so the proxy never reads it half written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_yaml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Add a key with a new secret, returning the secret, which is not stored
+    pub fn create(
+        &mut self,
+        name: &str,
+        scope: KeyScope,
+        expires_in_secs: Option<u64>,
+    ) -> Result<String> {
+        if self.keys.iter().any(|key| key.name == name) {
+            anyhow::bail!("Key {} already exists", name);
+        }
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let secret = format!("{}{}", SECRET_PREFIX, hex(&bytes));
+        let created_at = now();
+        self.keys.push(ApiKey {
+            name: name.to_string(),
+            hash: hash_secret(&secret),
+            scope,
+            created_at,
+            expires_at: expires_in_secs.map(|secs| created_at + secs),
+        });
+        Ok(secret)
+    }
+
+    pub fn revoke(&mut self, name: &str) -> Result<()> {
+        let count = self.keys.len();
+        self.keys.retain(|key| key.name != name);
+        if self.keys.len() == count {
+            anyhow::bail!("Key {} not found", name);
+        }
+        Ok(())
+    }
+}
+
+/// The keys of a keys file, reloaded when the file changes
+pub struct KeyStore {
+    path: PathBuf,
+    state: RwLock<Loaded>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    modified: Option<SystemTime>,
+    keys: Vec<ApiKey>,
+}
+
+impl KeyStore {
+    pub fn new(path: &str) -> Self {
+        let store = Self {
+            path: PathBuf::from(path),
+            state: RwLock::new(Loaded::default()),
+        };
+        store.reload();
+        store
+    }
+
+    /// Reload the file if it changed, keeping the previous keys when the new
+    /// contents are invalid until the file changes again
+    fn reload(&self) {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if self
+            .state
+            .read()
+            .is_ok_and(|state| state.modified == modified)
+        {
+            return;
+        }
+        let keys = match KeysFile::load(&self.path) {
+            Ok(file) => file.keys,
+            Err(e) => {
+                warn!("{:#}", e);
+                if let Ok(mut state) = self.state.write() {
+                    state.modified = modified;
+                }
+                return;
+            }
+        };
+        info!(
+            "Loaded {} API keys from {}",
+            keys.len(),
+            self.path.display()
+        );
+        if let Ok(mut state) = self.state.write() {
+            *state = Loaded { modified, keys };
+        }
+    }
+
+    /// The key a secret belongs to
+    pub fn find(&self, secret: &str) -> Option<ApiKey> {
+        self.reload();
+        let hash = hash_secret(secret);
+        let state = self.state.read().ok()?;
+        state
+            .keys
+            .iter()
+            .find(|key| bool::from(key.hash.as_bytes().ct_eq(hash.as_bytes())))
+            .cloned()
+    }
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    hex(digest(&SHA256, secret.as_bytes()).as_ref())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{:02x}", byte);
+        out
+    })
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AuthError, Authenticator};
+    use std::time::Duration;
+
+    /// A keys file path in a fresh temporary directory
+    struct TempKeys(PathBuf);
+
+    impl TempKeys {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("mcp-proxy-keys-{}", uuid::Uuid::new_v4()));
+            fs::create_dir(&dir).unwrap();
+            Self(dir.join("keys.yaml"))
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+
+        /// Save the file with a later modification time, so a reload sees it
+        /// even on file systems with coarse timestamps
+        fn save(&self, file: &KeysFile, age: u64) {
+            file.save(&self.0).unwrap();
+            self.touch(age);
+        }
+
+        fn touch(&self, age: u64) {
+            self.set_modified(SystemTime::now() + Duration::from_secs(age));
+        }
+
+        fn set_modified(&self, modified: SystemTime) {
+            fs::File::options()
+                .write(true)
+                .open(&self.0)
+                .and_then(|f| f.set_modified(modified))
+                .unwrap();
+        }
+    }
+
+    impl Drop for TempKeys {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    #[test]
+    fn secrets_are_stored_only_as_hashes() {
+        let mut file = KeysFile::default();
+        let secret = file.create("ci", KeyScope::default(), None).unwrap();
+        assert!(secret.starts_with(SECRET_PREFIX));
+        assert_eq!(file.keys[0].hash, hash_secret(&secret));
+        assert_eq!(file.keys[0].hash.len(), 64);
+        assert!(!serde_yaml::to_string(&file).unwrap().contains(&secret));
+
+        let other = file.create("deploy", KeyScope::default(), None).unwrap();
+        assert_ne!(secret, other);
+    }
+
+    #[test]
+    fn names_are_unique_and_revocation_needs_a_known_key() {
+        let mut file = KeysFile::default();
+        file.create("ci", KeyScope::default(), None).unwrap();
+        assert!(file.create("ci", KeyScope::default(), None).is_err());
+        assert!(file.revoke("missing").is_err());
+        file.revoke("ci").unwrap();
+        assert!(file.keys.is_empty());
+    }
+
+    #[test]
+    fn keys_expire() {
+        let mut file = KeysFile::default();
+        file.create("forever", KeyScope::default(), None).unwrap();
+        file.create("soon", KeyScope::default(), Some(3600))
+            .unwrap();
+        file.create("now", KeyScope::default(), Some(0)).unwrap();
+        let expired: Vec<bool> = file.keys.iter().map(ApiKey::is_expired).collect();
+        assert_eq!(expired, vec![false, false, true]);
+    }
+
+    #[test]
+    fn store_finds_keys_by_secret() {
+        let keys = TempKeys::new();
+        let mut file = KeysFile::default();
+        let scope = KeyScope {
+            servers: vec!["files".to_string()],
+            ..Default::default()
+        };
+        let secret = file.create("ci", scope.clone(), None).unwrap();
+        keys.save(&file, 0);
+
+        let store = KeyStore::new(keys.path());
+        let key = store.find(&secret).unwrap();
+        assert_eq!((key.name.as_str(), &key.scope), ("ci", &scope));
+        assert!(store.find("mcpk_wrong").is_none());
+        assert!(store.find(&key.hash).is_none());
+    }
+
+    #[test]
+    fn revoked_keys_stop_working_after_a_reload() {
+        let keys = TempKeys::new();
+        let mut file = KeysFile::default();
+        let revoked = file.create("ci", KeyScope::default(), None).unwrap();
+        let kept = file.create("deploy", KeyScope::default(), None).unwrap();
+        keys.save(&file, 0);
+        let store = KeyStore::new(keys.path());
+        assert!(store.find(&revoked).is_some());
+
+        file.revoke("ci").unwrap();
+        keys.save(&file, 1);
+        assert!(store.find(&revoked).is_none());
+        assert!(store.find(&kept).is_some());
+    }
+
+    #[test]
+    fn invalid_files_keep_the_previous_keys() {
+        let keys = TempKeys::new();
+        let mut file = KeysFile::default();
+        let secret = file.create("ci", KeyScope::default(), None).unwrap();
+        keys.save(&file, 0);
+        let store = KeyStore::new(keys.path());
+
+        fs::write(&keys.0, "keys: [not a key").unwrap();
+        keys.touch(1);
+        assert!(store.find(&secret).is_some());
+
+        // Parsed once, a file that did not change since is not read again
+        let modified = fs::metadata(&keys.0).unwrap().modified().unwrap();
+        let mut replacement = KeysFile::default();
+        let other = replacement
+            .create("deploy", KeyScope::default(), None)
+            .unwrap();
+        replacement.save(&keys.0).unwrap();
+        keys.set_modified(modified);
+        assert!(store.find(&other).is_none());
+        assert!(store.find(&secret).is_some());
+    }
+
+    #[test]
+    fn expired_keys_are_rejected() {
+        let keys = TempKeys::new();
+        let mut file = KeysFile::default();
+        let expired = file.create("old", KeyScope::default(), Some(0)).unwrap();
+        let valid = file.create("new", KeyScope::default(), Some(3600)).unwrap();
+        keys.save(&file, 0);
+
+        let auth = Authenticator::Keys(KeyStore::new(keys.path()));
+        assert!(matches!(
+            auth.verify(Some(&format!("Bearer {}", expired))),
+            Err(AuthError::Invalid(_))
+        ));
+        let identity = auth.verify(Some(&format!("Bearer {}", valid))).unwrap();
+        assert_eq!(identity.subject.as_deref(), Some("new"));
+        assert!(identity.key.is_some());
+    }
+}
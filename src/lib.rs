@@ -1,3 +1,23 @@
+pub mod admin;
+pub mod approval;
+pub mod auth;
+pub mod balancer;
+pub mod breaker;
+pub mod cache;
+pub mod composite;
+pub mod credentials;
+pub mod downstream;
+pub mod http;
+pub mod keys;
+pub mod limiter;
+pub mod output;
+pub mod policy;
+pub mod pool;
+pub mod ratelimit;
+pub mod retry;
+pub mod search;
 pub mod server;
+pub mod stdio;
+pub mod supervisor;
 pub use server::McpProxy;
 pub mod types;
@@ -0,0 +1,157 @@
+//! Per-server limits on concurrent tool calls with a bounded wait queue.
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::retry::ProxyError;
+use crate::types::ConcurrencyConfig;
+
+/// Snapshot of a limiter for status output
+#[derive(Debug, Clone, Serialize)]
+pub struct LimiterStatus {
+    pub in_flight: usize,
+    pub queued: usize,
+    pub max_in_flight: usize,
+    pub max_queue: usize,
+}
+
+pub struct ConcurrencyLimiter {
+    server: String,
+    config: ConcurrencyConfig,
+    slots: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+/// Decrements the queue length when a waiting call leaves the queue
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(server: &str, config: ConcurrencyConfig) -> Self {
+        Self {
+            server: server.to_string(),
+            slots: Arc::new(Semaphore::new(config.max_in_flight.max(1))),
+            config,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for a free slot, the call may run while the permit is held
+    pub async fn acquire(&self) -> anyhow::Result<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst);
+        let _queued = Queued(&self.queued);
+        if queued >= self.config.max_queue {
+            return Err(ProxyError(format!(
+                "Server {} is busy: {} calls in flight and {} queued",
+                self.server, self.config.max_in_flight, queued
+            ))
+            .into());
+        }
+
+        let timeout = Duration::from_millis(self.config.queue_timeout_ms);
+        let error = match tokio::time::timeout(timeout, self.slots.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => return Ok(permit),
+            Ok(Err(_)) => format!("Server {} is shutting down", self.server),
+            Err(_) => format!(
+                "Server {} is busy: no call slot became free within {}ms",
+                self.server, self.config.queue_timeout_ms
+            ),
+        };
+        Err(ProxyError(error).into())
+    }
+
+    pub fn status(&self) -> LimiterStatus {
+        let max_in_flight = self.config.max_in_flight.max(1);
+        LimiterStatus {
+            in_flight: max_in_flight - self.slots.available_permits(),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_in_flight,
+            max_queue: self.config.max_queue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_queue: usize, queue_timeout_ms: u64) -> Arc<ConcurrencyLimiter> {
+        Arc::new(ConcurrencyLimiter::new(
+            "files",
+            ConcurrencyConfig {
+                max_in_flight: 1,
+                max_queue,
+                queue_timeout_ms,
+            },
+        ))
+    }
+
+    /// Wait until `count` calls are queued
+    async fn queued(limiter: &ConcurrencyLimiter, count: usize) {
+        while limiter.status().queued < count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_calls_run_once_a_slot_is_free() {
+        let limiter = limiter(1, 60_000);
+        let permit = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.status().in_flight, 1);
+
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await.map(drop) }
+        });
+        queued(&limiter, 1).await;
+        drop(permit);
+        assert!(waiting.await.unwrap().is_ok());
+        assert_eq!(limiter.status().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn calls_beyond_the_queue_are_rejected() {
+        let limiter = limiter(1, 60_000);
+        let _permit = limiter.acquire().await.unwrap();
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await.map(drop) }
+        });
+        queued(&limiter, 1).await;
+
+        let error = limiter.acquire().await.unwrap_err();
+        assert!(error.to_string().contains("1 queued"), "{}", error);
+        assert!(error.is::<ProxyError>());
+        assert_eq!(limiter.status().queued, 1);
+        waiting.abort();
+    }
+
+    #[tokio::test]
+    async fn without_a_queue_busy_calls_fail_at_once() {
+        let limiter = limiter(0, 60_000);
+        let _permit = limiter.acquire().await.unwrap();
+        assert!(limiter.acquire().await.is_err());
+        assert_eq!(limiter.status().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn queued_calls_give_up_after_the_queue_timeout() {
+        let limiter = limiter(1, 10);
+        let _permit = limiter.acquire().await.unwrap();
+        let error = limiter.acquire().await.unwrap_err();
+        assert!(error.to_string().contains("within 10ms"), "{}", error);
+        assert!(error.is::<ProxyError>());
+        assert_eq!(limiter.status().queued, 0);
+    }
+}
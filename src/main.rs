@@ -1,13 +1,16 @@
-use std::{fs, sync::Arc};
-mod server;
-mod types;
+use std::{fs, path::Path, sync::Arc};
 
 use anyhow::Result;
-use async_mcp::run_http_server;
+use async_mcp::transport::ServerStdioTransport;
 use clap::{Parser, Subcommand};
-use server::McpProxy;
-use tracing::info;
-use types::ProxyServerConfig;
+use mcp_proxy::{
+    admin::admin_server,
+    downstream::proxy_server,
+    keys::{self, KeyScope, KeysFile},
+    types::{ProxyServerConfig, ProxyTransportAuth, RateLimit},
+    McpProxy,
+};
+use tracing::{error, info};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -24,8 +27,110 @@ struct Cli {
 enum Commands {
     /// List all configured servers
     List,
+    /// Connect to all servers and list their tools with the policy decision for each
+    Tools,
     /// Run the proxy server
-    Run,
+    Run {
+        /// Serve a single client over stdin and stdout instead of HTTP
+        #[arg(long)]
+        stdio: bool,
+    },
+    /// Manage the API keys in the configured keys file
+    Keys {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyCommands {
+    /// Create a key and print its secret, which is shown only once
+    Create {
+        name: String,
+        /// Server name glob the key may use, repeatable, all servers when not set
+        #[arg(long = "server")]
+        servers: Vec<String>,
+        /// Tool name glob the key may call, repeatable, all tools when not set
+        #[arg(long = "tool")]
+        tools: Vec<String>,
+        /// Tool calls allowed per period
+        #[arg(long)]
+        requests: Option<u32>,
+        /// Length of the rate limit period
+        #[arg(long, default_value_t = 60)]
+        period_secs: u64,
+        /// Days until the key expires, never when not set
+        #[arg(long)]
+        expires_in_days: Option<u64>,
+    },
+    /// List the keys without their secrets
+    List,
+    /// Delete a key, the proxy rejects it from its next request on
+    Revoke { name: String },
+}
+
+fn manage_keys(config: &ProxyServerConfig, command: KeyCommands) -> Result<()> {
+    let Some(ProxyTransportAuth::KeysFile(path)) = &config.auth else {
+        anyhow::bail!("Set `auth` to a KeysFile to manage API keys");
+    };
+    let path = Path::new(path);
+    let mut file = KeysFile::load(path)?;
+    match command {
+        KeyCommands::Create {
+            name,
+            servers,
+            tools,
+            requests,
+            period_secs,
+            expires_in_days,
+        } => {
+            let scope = KeyScope {
+                servers,
+                tools,
+                rate_limit: requests.map(|requests| RateLimit {
+                    requests,
+                    period_secs,
+                }),
+            };
+            let secret = file.create(&name, scope, expires_in_days.map(|days| days * 86400))?;
+            file.save(path)?;
+            println!("{}", secret);
+        }
+        KeyCommands::List => {
+            println!("Keys:");
+            for key in &file.keys {
+                let mut details = Vec::new();
+                if !key.scope.servers.is_empty() {
+                    details.push(format!("servers {}", key.scope.servers.join(", ")));
+                }
+                if !key.scope.tools.is_empty() {
+                    details.push(format!("tools {}", key.scope.tools.join(", ")));
+                }
+                if let Some(limit) = key.scope.rate_limit {
+                    details.push(format!("{} calls/{}s", limit.requests, limit.period_secs));
+                }
+                match key.expires_at {
+                    Some(_) if key.is_expired() => details.push("expired".to_string()),
+                    Some(expires_at) => details.push(format!(
+                        "expires in {}h",
+                        expires_at.saturating_sub(keys::now()).div_ceil(3600)
+                    )),
+                    None => {}
+                }
+                if details.is_empty() {
+                    println!("- {}", key.name);
+                } else {
+                    println!("- {}: {}", key.name, details.join("; "));
+                }
+            }
+        }
+        KeyCommands::Revoke { name } => {
+            file.revoke(&name)?;
+            file.save(path)?;
+            println!("Revoked key {}", name);
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
@@ -49,23 +154,42 @@ async fn main() -> Result<()> {
                 println!("- {}", name);
             }
         }
-        Commands::Run => {
+        Commands::Tools => {
+            let proxy = McpProxy::initialize(config).await?;
+            println!("Tools:");
+            for (name, decision) in proxy.tool_decisions().await {
+                println!("- {}: {}", name, decision);
+            }
+        }
+        Commands::Run { stdio } => {
             info!(
                 "Starting proxy server with {} servers",
                 config.servers.len()
             );
-            let port = config.port;
-            let proxy = McpProxy::initialize(config).await?;
+            let admin = config.admin.clone();
+            let proxy = McpProxy::initialize(config.clone()).await?;
 
-            run_http_server(port, None, move |transport| {
-                let proxy = proxy.clone();
-                async move {
-                    let server = proxy.build(transport).await?;
-                    Ok(server)
-                }
-            })
-            .await?;
+            if let Some(admin) = admin {
+                let server = admin_server(admin, proxy.clone())?;
+                tokio::spawn(async move {
+                    if let Err(e) = server.await {
+                        error!("Admin server failed: {:?}", e);
+                    }
+                });
+            }
+
+            if stdio {
+                // Stdout carries the protocol, logs go to stderr
+                let server = proxy.clone().build(ServerStdioTransport).await?;
+                let result = server.listen().await;
+                info!("Stdin closed, stopping upstream servers");
+                proxy.shutdown().await;
+                return result;
+            }
+
+            proxy_server(&config, proxy)?.await?;
         }
+        Commands::Keys { command } => manage_keys(&config, command)?,
     }
 
     Ok(())
@@ -0,0 +1,454 @@
+//! Tool output size limits.
+//!
+//! Oversized tool responses are either truncated with a marker or stored by the
+//! proxy and replaced with a resource link that can be read back in chunks
+//! through `resources/read`.
+use anyhow::{anyhow, Result};
+use async_mcp::types::{CallToolResponse, ResourceContents, ToolResponseContent};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use url::Url;
+
+use crate::auth::Identity;
+use crate::policy::Access;
+use crate::types::{OutputLimitConfig, OverflowAction};
+
+pub const OUTPUT_URI_PREFIX: &str = "mcp-proxy://output/";
+
+/// Number of spilled outputs kept before the oldest ones are dropped
+const MAX_STORED_OUTPUTS: usize = 64;
+
+/// The call that produced a stored output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputOwner {
+    /// Subject of the caller, only the same subject may read the output back
+    pub subject: Option<String>,
+    pub server: String,
+    pub tool: String,
+}
+
+impl OutputOwner {
+    /// Whether `identity` may read the output, which needs the same access as
+    /// the call that produced it
+    fn readable_by(&self, identity: &Identity, access: &Access<'_>) -> bool {
+        self.subject == identity.subject && access.allows_tool(&self.server, &self.tool)
+    }
+}
+
+struct StoredOutput {
+    owner: OutputOwner,
+    mime_type: Option<String>,
+    is_blob: bool,
+    chunks: Vec<String>,
+}
+
+/// Spilled tool outputs, addressable by resource URI
+#[derive(Default)]
+pub struct OutputStore {
+    outputs: HashMap<String, StoredOutput>,
+    order: VecDeque<String>,
+}
+
+/// Response to a `resources/read` request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadResourceResponse {
+    pub contents: Vec<ResourceChunk>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceChunk {
+    pub uri: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+impl OutputStore {
+    fn insert(&mut self, output: StoredOutput) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.outputs.insert(id.clone(), output);
+        self.order.push_back(id.clone());
+        while self.order.len() > MAX_STORED_OUTPUTS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.outputs.remove(&oldest);
+            }
+        }
+        id
+    }
+
+    /// Read one chunk of a stored output, `uri` is `mcp-proxy://output/<id>?chunk=<n>`.
+    ///
+    /// Outputs of other callers, or of tools the caller may no longer use, are
+    /// reported as missing.
+    pub fn read(
+        &self,
+        uri: &Url,
+        identity: &Identity,
+        access: &Access<'_>,
+    ) -> Result<ReadResourceResponse> {
+        let id = uri
+            .as_str()
+            .strip_prefix(OUTPUT_URI_PREFIX)
+            .map(|rest| rest.split('?').next().unwrap_or_default())
+            .ok_or_else(|| anyhow!("Resource {} not found", uri))?;
+        let output = self
+            .outputs
+            .get(id)
+            .filter(|output| output.owner.readable_by(identity, access))
+            .ok_or_else(|| anyhow!("Stored output {} not found or expired", id))?;
+        let chunk: usize = uri
+            .query_pairs()
+            .find(|(key, _)| key == "chunk")
+            .map(|(_, value)| value.parse())
+            .transpose()?
+            .unwrap_or(0);
+        let data = output.chunks.get(chunk).ok_or_else(|| {
+            anyhow!(
+                "Chunk {} out of range, output has {} chunks",
+                chunk,
+                output.chunks.len()
+            )
+        })?;
+
+        let (text, blob) = if output.is_blob {
+            (None, Some(data.clone()))
+        } else {
+            (Some(data.clone()), None)
+        };
+        Ok(ReadResourceResponse {
+            contents: vec![ResourceChunk {
+                uri: uri.clone(),
+                mime_type: output.mime_type.clone(),
+                text,
+                blob,
+            }],
+            meta: Some(serde_json::json!({ "chunk": chunk, "chunks": output.chunks.len() })),
+        })
+    }
+}
+
+fn content_size(content: &ToolResponseContent) -> usize {
+    match content {
+        ToolResponseContent::Text { text } => text.len(),
+        ToolResponseContent::Image { data, .. } => data.len(),
+        ToolResponseContent::Resource { resource } => resource.uri.as_str().len(),
+    }
+}
+
+/// Split `data` into chunks of at most `size` bytes on char boundaries
+fn split_chunks(data: &str, size: usize) -> Vec<String> {
+    let size = size.max(4);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let mut end = (start + size).min(data.len());
+        while !data.is_char_boundary(end) {
+            end -= 1;
+        }
+        chunks.push(data[start..end].to_string());
+        start = end;
+    }
+    chunks
+}
+
+fn truncate_str(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Enforce `limit` on a tool response
+pub fn apply_limit(
+    response: CallToolResponse,
+    limit: &OutputLimitConfig,
+    owner: &OutputOwner,
+    store: &mut OutputStore,
+) -> CallToolResponse {
+    let total: usize = response.content.iter().map(content_size).sum();
+    if total <= limit.max_bytes {
+        return response;
+    }
+
+    let mut remaining = limit.max_bytes;
+    let mut content = Vec::new();
+    let mut omitted = 0;
+    for item in response.content {
+        let size = content_size(&item);
+        if size <= remaining {
+            remaining -= size;
+            content.push(item);
+            continue;
+        }
+
+        match limit.overflow {
+            OverflowAction::Truncate => match item {
+                ToolResponseContent::Text { text } => {
+                    let kept = truncate_str(&text, remaining);
+                    omitted += text.len() - kept.len();
+                    remaining -= kept.len();
+                    if !kept.is_empty() {
+                        content.push(ToolResponseContent::Text {
+                            text: kept.to_string(),
+                        });
+                    }
+                }
+                _ => omitted += size,
+            },
+            OverflowAction::Resource => {
+                let (data, mime_type, is_blob) = match item {
+                    ToolResponseContent::Text { text } => {
+                        (text, Some("text/plain".to_string()), false)
+                    }
+                    ToolResponseContent::Image { data, mime_type } => (data, Some(mime_type), true),
+                    resource => {
+                        content.push(resource);
+                        continue;
+                    }
+                };
+                // Keep base64 chunks independently decodable
+                let chunk_bytes = if is_blob {
+                    limit.chunk_bytes / 4 * 4
+                } else {
+                    limit.chunk_bytes
+                };
+                let chunks = split_chunks(&data, chunk_bytes);
+                let chunk_count = chunks.len();
+                let id = store.insert(StoredOutput {
+                    owner: owner.clone(),
+                    mime_type: mime_type.clone(),
+                    is_blob,
+                    chunks,
+                });
+                let uri = format!("{OUTPUT_URI_PREFIX}{id}");
+                content.push(ToolResponseContent::Text {
+                    text: format!(
+                        "[Output of {} bytes exceeds the {} byte limit. Read it with resources/read from {}?chunk=<n>, n = 0..{}]",
+                        data.len(),
+                        limit.max_bytes,
+                        uri,
+                        chunk_count
+                    ),
+                });
+                if let Ok(uri) = Url::parse(&uri) {
+                    content.push(ToolResponseContent::Resource {
+                        resource: ResourceContents { uri, mime_type },
+                    });
+                }
+            }
+        }
+    }
+
+    if omitted > 0 {
+        content.push(ToolResponseContent::Text {
+            text: format!(
+                "\n[Truncated by mcp-proxy: {} of {} bytes omitted, limit is {} bytes]",
+                omitted, total, limit.max_bytes
+            ),
+        });
+    }
+
+    CallToolResponse {
+        content,
+        is_error: response.is_error,
+        meta: response.meta,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AuthorizationConfig;
+    use serde_json::json;
+
+    fn text(text: &str) -> ToolResponseContent {
+        ToolResponseContent::Text {
+            text: text.to_string(),
+        }
+    }
+
+    fn response(content: Vec<ToolResponseContent>) -> CallToolResponse {
+        CallToolResponse {
+            content,
+            is_error: None,
+            meta: None,
+        }
+    }
+
+    fn limit(max_bytes: usize, overflow: OverflowAction, chunk_bytes: usize) -> OutputLimitConfig {
+        OutputLimitConfig {
+            max_bytes,
+            overflow,
+            chunk_bytes,
+        }
+    }
+
+    fn owner() -> OutputOwner {
+        OutputOwner {
+            subject: Some("alice".to_string()),
+            server: "files".to_string(),
+            tool: "read".to_string(),
+        }
+    }
+
+    fn caller(subject: &str) -> Identity {
+        Identity {
+            subject: Some(subject.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn stored(chunks: Vec<String>) -> StoredOutput {
+        StoredOutput {
+            owner: owner(),
+            mime_type: None,
+            is_blob: false,
+            chunks,
+        }
+    }
+
+    fn texts(response: &CallToolResponse) -> Vec<&str> {
+        response
+            .content
+            .iter()
+            .filter_map(|content| match content {
+                ToolResponseContent::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn responses_within_the_limit_are_unchanged() {
+        let mut store = OutputStore::default();
+        let limited = apply_limit(
+            response(vec![text("hello")]),
+            &limit(5, OverflowAction::Truncate, 10),
+            &owner(),
+            &mut store,
+        );
+        assert_eq!(texts(&limited), ["hello"]);
+    }
+
+    #[test]
+    fn truncation_keeps_the_limit_and_adds_a_marker() {
+        let mut store = OutputStore::default();
+        let limited = apply_limit(
+            response(vec![text("abcd"), text("efghij")]),
+            &limit(6, OverflowAction::Truncate, 10),
+            &owner(),
+            &mut store,
+        );
+        let texts = texts(&limited);
+        assert_eq!(texts[..2], ["abcd", "ef"]);
+        assert!(texts[2].contains("4 of 10 bytes omitted, limit is 6 bytes"));
+    }
+
+    #[test]
+    fn truncation_respects_char_boundaries() {
+        let mut store = OutputStore::default();
+        let limited = apply_limit(
+            response(vec![text("ééé")]),
+            &limit(3, OverflowAction::Truncate, 10),
+            &owner(),
+            &mut store,
+        );
+        assert_eq!(texts(&limited)[0], "é");
+    }
+
+    #[test]
+    fn overflow_is_stored_and_read_back_in_chunks() {
+        let mut store = OutputStore::default();
+        let data = "x".repeat(25);
+        let limited = apply_limit(
+            response(vec![text(&data)]),
+            &limit(10, OverflowAction::Resource, 10),
+            &owner(),
+            &mut store,
+        );
+        let Some(ToolResponseContent::Resource { resource }) = limited.content.last() else {
+            panic!("expected a resource link");
+        };
+        assert!(resource.uri.as_str().starts_with(OUTPUT_URI_PREFIX));
+
+        let alice = caller("alice");
+        let access = Access::new(None, &alice);
+        let mut read = String::new();
+        for chunk in 0..3 {
+            let uri = Url::parse(&format!("{}?chunk={}", resource.uri, chunk)).unwrap();
+            let response = store.read(&uri, &alice, &access).unwrap();
+            assert_eq!(response.meta.unwrap()["chunks"], 3);
+            read.push_str(response.contents[0].text.as_deref().unwrap());
+        }
+        assert_eq!(read, data);
+
+        let uri = Url::parse(&format!("{}?chunk=3", resource.uri)).unwrap();
+        assert!(store.read(&uri, &alice, &access).is_err());
+    }
+
+    #[test]
+    fn oldest_outputs_are_dropped() {
+        let mut store = OutputStore::default();
+        let first = store.insert(stored(vec!["a".to_string()]));
+        for _ in 0..MAX_STORED_OUTPUTS {
+            store.insert(stored(Vec::new()));
+        }
+        let uri = Url::parse(&format!("{OUTPUT_URI_PREFIX}{first}")).unwrap();
+        let alice = caller("alice");
+        assert!(store
+            .read(&uri, &alice, &Access::new(None, &alice))
+            .is_err());
+    }
+
+    #[test]
+    fn chunks_split_on_char_boundaries() {
+        assert_eq!(split_chunks("ééé", 5), ["éé", "é"]);
+        assert!(split_chunks("", 5).is_empty());
+    }
+
+    #[test]
+    fn outputs_are_only_readable_by_their_caller() {
+        let mut store = OutputStore::default();
+        let id = store.insert(stored(vec!["secret".to_string()]));
+        let uri = Url::parse(&format!("{OUTPUT_URI_PREFIX}{id}")).unwrap();
+
+        let alice = caller("alice");
+        assert!(store.read(&uri, &alice, &Access::new(None, &alice)).is_ok());
+
+        let bob = caller("bob");
+        let error = store
+            .read(&uri, &bob, &Access::new(None, &bob))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Stored output {} not found or expired", id)
+        );
+        let anonymous = Identity::default();
+        assert!(store
+            .read(&uri, &anonymous, &Access::new(None, &anonymous))
+            .is_err());
+    }
+
+    #[test]
+    fn outputs_need_access_to_the_tool_that_produced_them() {
+        let mut store = OutputStore::default();
+        let id = store.insert(stored(vec!["secret".to_string()]));
+        let uri = Url::parse(&format!("{OUTPUT_URI_PREFIX}{id}")).unwrap();
+
+        let config: AuthorizationConfig = serde_json::from_value(json!({
+            "rules": [{ "subjects": ["alice"], "servers": ["files"], "tools": ["list"] }]
+        }))
+        .unwrap();
+        let alice = caller("alice");
+        assert!(store
+            .read(&uri, &alice, &Access::new(Some(&config), &alice))
+            .is_err());
+    }
+}
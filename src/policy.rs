@@ -0,0 +1,467 @@
+//! Policies deciding which tools are exposed and callable.
+use std::collections::HashMap;
+use std::fmt;
+use tracing::warn;
+
+use crate::auth::Identity;
+use crate::keys::KeyScope;
+use crate::types::{AccessRule, AuthorizationConfig, EnvPolicy, EnvViolation, ToolAnnotations};
+
+/// Whether a tool may be listed and called
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolDecision {
+    Allowed,
+    Blocked(String),
+}
+
+impl ToolDecision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, ToolDecision::Allowed)
+    }
+}
+
+impl fmt::Display for ToolDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolDecision::Allowed => write!(f, "allowed"),
+            ToolDecision::Blocked(reason) => write!(f, "blocked ({})", reason),
+        }
+    }
+}
+
+/// Match `name` against a glob `pattern` supporting `*` and `?`
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// What an identity may use under the authorization rules and the scope of
+/// its API key.
+///
+/// Without authorization configured everything is allowed, otherwise only
+/// what one of the rules matching the identity grants.
+pub struct Access<'a> {
+    rules: Option<Vec<&'a AccessRule>>,
+    key: Option<&'a KeyScope>,
+}
+
+impl<'a> Access<'a> {
+    pub fn new(config: Option<&'a AuthorizationConfig>, identity: &'a Identity) -> Self {
+        Self {
+            key: identity.key.as_ref(),
+            rules: config.map(|config| {
+                config
+                    .rules
+                    .iter()
+                    .filter(|rule| rule_matches(rule, identity))
+                    .collect()
+            }),
+        }
+    }
+
+    pub fn allows_tool(&self, server_name: &str, tool_name: &str) -> bool {
+        let in_scope = self.key.is_none_or(|key| {
+            key.tools.is_empty()
+                || key
+                    .tools
+                    .iter()
+                    .any(|pattern| glob_match(pattern, tool_name))
+        });
+        in_scope
+            && self.allows(server_name, |rule| {
+                rule.tools.is_empty()
+                    || rule
+                        .tools
+                        .iter()
+                        .any(|pattern| glob_match(pattern, tool_name))
+            })
+    }
+
+    pub fn allows_resource(&self, server_name: &str, uri: &str) -> bool {
+        self.allows(server_name, |rule| {
+            rule.resources.is_empty()
+                || rule
+                    .resources
+                    .iter()
+                    .any(|prefix| uri.starts_with(prefix.as_str()))
+        })
+    }
+
+    fn allows(&self, server_name: &str, grants: impl Fn(&AccessRule) -> bool) -> bool {
+        if self.key.is_some_and(|key| {
+            !key.servers.is_empty()
+                && !key
+                    .servers
+                    .iter()
+                    .any(|pattern| glob_match(pattern, server_name))
+        }) {
+            return false;
+        }
+        let Some(rules) = &self.rules else {
+            return true;
+        };
+        rules.iter().any(|rule| {
+            rule.servers
+                .iter()
+                .any(|pattern| glob_match(pattern, server_name))
+                && grants(rule)
+        })
+    }
+}
+
+/// A rule without subjects or groups applies to every identity
+fn rule_matches(rule: &AccessRule, identity: &Identity) -> bool {
+    if rule.subjects.is_empty() && rule.groups.is_empty() {
+        return true;
+    }
+    let subject = identity.subject.as_deref().is_some_and(|subject| {
+        rule.subjects
+            .iter()
+            .any(|pattern| glob_match(pattern, subject))
+    });
+    subject
+        || identity
+            .groups
+            .iter()
+            .any(|group| rule.groups.contains(group))
+}
+
+/// Decide whether a tool is allowed in read-only mode.
+///
+/// Only tools annotated with `readOnlyHint: true` pass, and only if their name
+/// does not match one of the configured mutating patterns.
+pub fn read_only_decision(
+    tool_name: &str,
+    annotations: Option<&ToolAnnotations>,
+    mutating_patterns: &[String],
+) -> ToolDecision {
+    if let Some(pattern) = mutating_patterns
+        .iter()
+        .find(|pattern| glob_match(pattern, tool_name))
+    {
+        return ToolDecision::Blocked(format!("read-only: name matches {}", pattern));
+    }
+    match annotations.and_then(|a| a.read_only_hint) {
+        Some(true) => ToolDecision::Allowed,
+        Some(false) => ToolDecision::Blocked("read-only: readOnlyHint is false".to_string()),
+        None => ToolDecision::Blocked("read-only: no readOnlyHint annotation".to_string()),
+    }
+}
+
+/// Check caller-supplied environment variables against a server's policy.
+///
+/// Without a policy every variable is refused. Refused variables fail the call
+/// or are dropped, depending on `on_violation`. Returns `None` when nothing is
+/// left to inject.
+pub fn filter_env(
+    server_name: &str,
+    env_vars: Option<HashMap<String, String>>,
+    policy: Option<&EnvPolicy>,
+) -> anyhow::Result<Option<HashMap<String, String>>> {
+    let Some(env_vars) = env_vars else {
+        return Ok(None);
+    };
+    let default_policy = EnvPolicy::default();
+    let policy = policy.unwrap_or(&default_policy);
+
+    let mut allowed = HashMap::new();
+    let mut refused = Vec::new();
+    for (key, value) in env_vars {
+        let matches = policy
+            .allow
+            .get(&key)
+            .is_some_and(|pattern| pattern.is_match(&value));
+        if matches {
+            allowed.insert(key, value);
+        } else {
+            refused.push(key);
+        }
+    }
+
+    if !refused.is_empty() {
+        refused.sort();
+        let keys = refused.join(", ");
+        match policy.on_violation {
+            EnvViolation::Reject => {
+                warn!(
+                    "Rejected call to server {} with disallowed env vars: {}",
+                    server_name, keys
+                );
+                anyhow::bail!(
+                    "Environment variables not allowed for server {}: {}",
+                    server_name,
+                    keys
+                );
+            }
+            EnvViolation::Strip => warn!(
+                "Stripped disallowed env vars from call to server {}: {}",
+                server_name, keys
+            ),
+        }
+    }
+    Ok((!allowed.is_empty()).then_some(allowed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn annotations(read_only_hint: Option<bool>) -> ToolAnnotations {
+        ToolAnnotations {
+            read_only_hint,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn read_only_tools_are_allowed() {
+        let hint = annotations(Some(true));
+        assert_eq!(
+            read_only_decision("get_file", Some(&hint), &[]),
+            ToolDecision::Allowed
+        );
+    }
+
+    #[test]
+    fn tools_without_a_read_only_hint_are_blocked() {
+        assert!(!read_only_decision("get_file", None, &[]).is_allowed());
+        assert!(!read_only_decision("get_file", Some(&annotations(None)), &[]).is_allowed());
+        assert_eq!(
+            read_only_decision("put_file", Some(&annotations(Some(false))), &[]),
+            ToolDecision::Blocked("read-only: readOnlyHint is false".to_string())
+        );
+    }
+
+    #[test]
+    fn mutating_patterns_override_the_hint() {
+        let patterns = vec!["write_*".to_string(), "*_delete".to_string()];
+        let hint = annotations(Some(true));
+        assert_eq!(
+            read_only_decision("write_file", Some(&hint), &patterns),
+            ToolDecision::Blocked("read-only: name matches write_*".to_string())
+        );
+        assert!(!read_only_decision("file_delete", Some(&hint), &patterns).is_allowed());
+        assert!(read_only_decision("read_file", Some(&hint), &patterns).is_allowed());
+    }
+
+    fn env_policy(on_violation: &str) -> EnvPolicy {
+        serde_json::from_value(json!({
+            "allow": { "REGION": "eu-[a-z]+-[0-9]", "DEBUG": "true|false" },
+            "on_violation": on_violation,
+        }))
+        .unwrap()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Option<HashMap<String, String>> {
+        Some(
+            vars.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn env_vars_are_refused_without_a_policy() {
+        let error = filter_env("files", env(&[("HOME", "/tmp")]), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Environment variables not allowed for server files: HOME"
+        );
+        assert_eq!(filter_env("files", None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn allowed_values_must_match_the_whole_pattern() {
+        let policy = env_policy("reject");
+        let allowed = filter_env(
+            "files",
+            env(&[("REGION", "eu-west-1"), ("DEBUG", "true")]),
+            Some(&policy),
+        )
+        .unwrap();
+        assert_eq!(allowed, env(&[("REGION", "eu-west-1"), ("DEBUG", "true")]));
+
+        for (key, value) in [
+            ("REGION", "eu-west-1; rm -rf /"),
+            ("REGION", "xeu-west-1"),
+            ("DEBUG", "truefalse"),
+        ] {
+            assert!(
+                filter_env("files", env(&[(key, value)]), Some(&policy)).is_err(),
+                "{}={} was allowed",
+                key,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let policy = env_policy("reject");
+        let error = filter_env(
+            "files",
+            env(&[
+                ("REGION", "eu-west-1"),
+                ("PATH", "/evil"),
+                ("LD_PRELOAD", "x"),
+            ]),
+            Some(&policy),
+        )
+        .unwrap_err();
+        assert!(error.to_string().ends_with("LD_PRELOAD, PATH"), "{}", error);
+    }
+
+    #[test]
+    fn strip_drops_only_the_refused_vars() {
+        let policy = env_policy("strip");
+        let allowed = filter_env(
+            "files",
+            env(&[("REGION", "eu-west-1"), ("PATH", "/evil"), ("DEBUG", "yes")]),
+            Some(&policy),
+        )
+        .unwrap();
+        assert_eq!(allowed, env(&[("REGION", "eu-west-1")]));
+
+        let nothing_left = filter_env("files", env(&[("PATH", "/evil")]), Some(&policy));
+        assert_eq!(nothing_left.unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_patterns_fail_when_the_config_loads() {
+        let error = serde_json::from_value::<EnvPolicy>(json!({ "allow": { "REGION": "eu-(" } }))
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("invalid env_policy pattern"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("read_*", "read_file"));
+        assert!(glob_match("read_*", "read_"));
+        assert!(!glob_match("read_*", "xread_file"));
+        assert!(glob_match("*_file", "read_file"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+        assert!(glob_match("file?", "file1"));
+        assert!(!glob_match("file?", "file"));
+        assert!(glob_match("**", "x"));
+    }
+
+    #[test]
+    fn glob_without_wildcards_matches_exactly() {
+        assert!(glob_match("files", "files"));
+        assert!(!glob_match("files", "files2"));
+        assert!(!glob_match("files", "file"));
+        assert!(!glob_match("", "files"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("files", ""));
+    }
+
+    fn authorization(rules: serde_json::Value) -> AuthorizationConfig {
+        serde_json::from_value(json!({ "rules": rules })).unwrap()
+    }
+
+    fn subject(name: &str, groups: &[&str]) -> Identity {
+        Identity {
+            subject: Some(name.to_string()),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            key: None,
+        }
+    }
+
+    #[test]
+    fn everything_is_allowed_without_authorization() {
+        let identity = Identity::default();
+        let access = Access::new(None, &identity);
+        assert!(access.allows_tool("files", "delete"));
+        assert!(access.allows_resource("files", "file:///etc/passwd"));
+    }
+
+    #[test]
+    fn nothing_is_allowed_without_a_matching_rule() {
+        let config = authorization(json!([{ "subjects": ["alice"], "servers": ["*"] }]));
+        let bob = subject("bob", &[]);
+        let access = Access::new(Some(&config), &bob);
+        assert!(!access.allows_tool("files", "read"));
+        assert!(!access.allows_resource("files", "file:///tmp"));
+
+        let anonymous = Identity::default();
+        assert!(!Access::new(Some(&config), &anonymous).allows_tool("files", "read"));
+        assert!(
+            !Access::new(Some(&authorization(json!([]))), &anonymous).allows_tool("files", "read")
+        );
+    }
+
+    #[test]
+    fn rules_grant_tools_and_resources_on_their_servers() {
+        let config = authorization(json!([
+            { "groups": ["ops"], "servers": ["*"] },
+            {
+                "subjects": ["dev-*"],
+                "servers": ["files"],
+                "tools": ["read_*"],
+                "resources": ["file:///srv/"],
+            },
+            { "servers": ["docs"], "tools": ["search"] },
+        ]));
+
+        let dev = subject("dev-alice", &[]);
+        let access = Access::new(Some(&config), &dev);
+        assert!(access.allows_tool("files", "read_file"));
+        assert!(!access.allows_tool("files", "write_file"));
+        assert!(!access.allows_tool("shell", "read_file"));
+        assert!(access.allows_resource("files", "file:///srv/data"));
+        assert!(!access.allows_resource("files", "file:///etc/passwd"));
+        assert!(access.allows_tool("docs", "search"));
+        assert!(!access.allows_tool("docs", "index"));
+
+        let ops = subject("carol", &["ops"]);
+        let access = Access::new(Some(&config), &ops);
+        assert!(access.allows_tool("shell", "exec"));
+        assert!(access.allows_resource("files", "file:///etc/passwd"));
+    }
+
+    #[test]
+    fn key_scope_narrows_what_rules_allow() {
+        let config = authorization(json!([{ "servers": ["*"] }]));
+        let mut identity = subject("ci", &[]);
+        identity.key = Some(KeyScope {
+            servers: vec!["files".to_string()],
+            tools: vec!["read_*".to_string()],
+            rate_limit: None,
+        });
+        for config in [None, Some(&config)] {
+            let access = Access::new(config, &identity);
+            assert!(access.allows_tool("files", "read_file"));
+            assert!(!access.allows_tool("files", "write_file"));
+            assert!(!access.allows_tool("shell", "read_file"));
+            assert!(access.allows_resource("files", "file:///tmp"));
+            assert!(!access.allows_resource("shell", "file:///tmp"));
+        }
+    }
+}
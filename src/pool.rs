@@ -0,0 +1,481 @@
+//! Pools of identical stdio worker processes behind one server.
+//!
+//! Calls go to the least busy worker. New workers are spawned while every
+//! worker is busy, up to `max`. Workers that exit are dropped and replaced on
+//! the next call, and workers idle for `idle_secs` are stopped down to `min`.
+//! With the server's `idle_timeout` the last ones are stopped as well.
+use async_mcp::client::Client;
+use async_mcp::transport::Transport;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, info, warn};
+
+use crate::retry::ProxyError;
+use crate::types::PoolConfig;
+
+/// Starts a new worker with the given environment variables
+pub type Connector<T> = Box<
+    dyn Fn(Option<HashMap<String, String>>) -> BoxFuture<'static, anyhow::Result<(Client<T>, T)>>
+        + Send
+        + Sync,
+>;
+
+struct Worker<T: Transport> {
+    id: u64,
+    client: Client<T>,
+    transport: T,
+    in_flight: Arc<AtomicUsize>,
+    last_used: Arc<std::sync::Mutex<Instant>>,
+}
+
+/// Snapshot of a pool for status output
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStatus {
+    pub workers: usize,
+    pub in_flight: Vec<usize>,
+    pub min: usize,
+    pub max: usize,
+}
+
+pub struct WorkerPool<T: Transport> {
+    server: String,
+    config: PoolConfig,
+    connect: Connector<T>,
+    /// Stop even the last `min` workers after this long without calls
+    idle_timeout: Option<Duration>,
+    workers: Mutex<Vec<Worker<T>>>,
+    /// Workers being started, reserved under the `workers` lock
+    spawning: AtomicUsize,
+    /// Signalled whenever workers finish starting
+    spawned: Notify,
+    next_id: AtomicU64,
+    reaper_started: AtomicBool,
+    closed: AtomicBool,
+}
+
+/// A worker checked out for one request, released when dropped
+pub struct Lease<T: Transport> {
+    client: Client<T>,
+    in_flight: Arc<AtomicUsize>,
+    last_used: Arc<std::sync::Mutex<Instant>>,
+}
+
+impl<T: Transport + Clone> Worker<T> {
+    /// Whether the worker has no calls and had none for `min_idle`
+    fn is_idle(&self, min_idle: Duration) -> bool {
+        self.in_flight.load(Ordering::SeqCst) == 0
+            && self.last_used.lock().is_ok_and(|t| t.elapsed() >= min_idle)
+    }
+
+    fn lease(&self) -> Lease<T> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Lease {
+            client: self.client.clone(),
+            in_flight: self.in_flight.clone(),
+            last_used: self.last_used.clone(),
+        }
+    }
+}
+
+impl<T: Transport> Deref for Lease<T> {
+    type Target = Client<T>;
+
+    fn deref(&self) -> &Client<T> {
+        &self.client
+    }
+}
+
+impl<T: Transport> Drop for Lease<T> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if let Ok(mut last_used) = self.last_used.lock() {
+            *last_used = Instant::now();
+        }
+    }
+}
+
+impl<T: Transport + Clone> WorkerPool<T> {
+    pub fn new(
+        server: &str,
+        config: PoolConfig,
+        connect: Connector<T>,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            server: server.to_string(),
+            config,
+            connect,
+            idle_timeout,
+            workers: Mutex::new(Vec::new()),
+            spawning: AtomicUsize::new(0),
+            spawned: Notify::new(),
+            next_id: AtomicU64::new(0),
+            reaper_started: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn max(&self) -> usize {
+        self.config.max.max(self.config.min).max(1)
+    }
+
+    /// Check out the least busy worker, spawning one if all are busy
+    pub async fn checkout(
+        self: &Arc<Self>,
+        env_vars: Option<HashMap<String, String>>,
+    ) -> anyhow::Result<Lease<T>> {
+        if !self.reaper_started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(Self::reap(
+                Arc::downgrade(self),
+                self.idle_secs(),
+                self.idle_timeout,
+            ));
+        }
+
+        loop {
+            let workers = self.workers.lock().await;
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(
+                    ProxyError(format!("Pool for server {} is shut down", self.server)).into(),
+                );
+            }
+            let started = workers.len() + self.spawning.load(Ordering::SeqCst);
+            let missing = self.config.min.saturating_sub(started);
+            let least_busy = workers
+                .iter()
+                .min_by_key(|w| w.in_flight.load(Ordering::SeqCst));
+            let count = match least_busy {
+                _ if missing > 0 => missing,
+                Some(worker) if worker.in_flight.load(Ordering::SeqCst) == 0 => {
+                    return Ok(worker.lease())
+                }
+                _ if started < self.max() => 1,
+                Some(worker) => return Ok(worker.lease()),
+                None => {
+                    // Every slot is taken by a worker still starting
+                    let spawned = self.spawned.notified();
+                    drop(workers);
+                    spawned.await;
+                    continue;
+                }
+            };
+
+            // Reserve the slots, then start the workers without holding the lock
+            self.spawning.fetch_add(count, Ordering::SeqCst);
+            drop(workers);
+            let results =
+                futures::future::join_all((0..count).map(|_| self.spawn(env_vars.clone()))).await;
+            let mut workers = self.workers.lock().await;
+            self.spawning.fetch_sub(count, Ordering::SeqCst);
+            self.spawned.notify_waiters();
+            let mut failure = None;
+            for result in results {
+                match result {
+                    Ok(worker) if self.closed.load(Ordering::SeqCst) => {
+                        tokio::spawn(async move {
+                            let _ = worker.transport.close().await;
+                        });
+                    }
+                    Ok(worker) => workers.push(worker),
+                    Err(e) => failure = Some(e),
+                }
+            }
+            if let Some(e) = failure {
+                return Err(e);
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                continue;
+            }
+            if missing == 0 {
+                info!(
+                    "Scaled pool for server {} up to {} workers",
+                    self.server,
+                    workers.len()
+                );
+            }
+            if let Some(worker) = workers.last() {
+                return Ok(worker.lease());
+            }
+        }
+    }
+
+    async fn spawn(
+        self: &Arc<Self>,
+        env_vars: Option<HashMap<String, String>>,
+    ) -> anyhow::Result<Worker<T>> {
+        let (client, transport) = (self.connect)(env_vars).await?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        debug!("Started worker {} for server {}", id, self.server);
+
+        let pool = Arc::downgrade(self);
+        let listener = client.clone();
+        tokio::spawn(async move {
+            let result = listener.start().await;
+            if let Some(pool) = pool.upgrade() {
+                pool.remove(id, result).await;
+            }
+        });
+
+        Ok(Worker {
+            id,
+            client,
+            transport,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            last_used: Arc::new(std::sync::Mutex::new(Instant::now())),
+        })
+    }
+
+    /// Drop a worker whose connection ended, the next checkout replaces it
+    async fn remove(&self, id: u64, result: anyhow::Result<()>) {
+        let mut workers = self.workers.lock().await;
+        let Some(index) = workers.iter().position(|w| w.id == id) else {
+            // Stopped by the reaper
+            return;
+        };
+        match result {
+            Ok(()) => warn!("Worker {} of server {} exited", id, self.server),
+            Err(e) => warn!("Worker {} of server {} failed: {}", id, self.server, e),
+        }
+        workers.remove(index);
+    }
+
+    fn idle_secs(&self) -> Duration {
+        Duration::from_secs(self.config.idle_secs.max(1))
+    }
+
+    /// Periodically stop workers idle for longer than `idle_secs` down to
+    /// `min`, and all of them once idle for the server's `idle_timeout`
+    async fn reap(pool: Weak<Self>, idle: Duration, idle_timeout: Option<Duration>) {
+        let idle = idle_timeout.map_or(idle, |idle_timeout| idle.min(idle_timeout));
+        let mut interval = tokio::time::interval(idle / 2);
+        loop {
+            interval.tick().await;
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            let mut workers = pool.workers.lock().await;
+            pool.shrink(&mut workers, idle, idle_timeout);
+        }
+    }
+
+    /// Stop workers without calls for `min_idle` down to `min`
+    pub async fn stop_idle(&self, min_idle: Duration) {
+        let mut workers = self.workers.lock().await;
+        self.shrink(&mut workers, min_idle, None);
+    }
+
+    /// Stop workers idle for `idle` down to `min`, and the rest once idle for
+    /// `idle_timeout`
+    fn shrink(&self, workers: &mut Vec<Worker<T>>, idle: Duration, idle_timeout: Option<Duration>) {
+        loop {
+            let min_idle = match idle_timeout {
+                _ if workers.len() > self.config.min => idle,
+                Some(idle_timeout) => idle_timeout,
+                None => return,
+            };
+            let Some(index) = workers.iter().position(|w| w.is_idle(min_idle)) else {
+                return;
+            };
+            self.stop(workers, index);
+        }
+    }
+
+    fn stop(&self, workers: &mut Vec<Worker<T>>, index: usize) {
+        let worker = workers.remove(index);
+        info!(
+            "Stopping idle worker {} of server {}, {} left",
+            worker.id,
+            self.server,
+            workers.len()
+        );
+        tokio::spawn(async move {
+            if let Err(e) = worker.transport.close().await {
+                warn!("Failed to stop idle worker: {:?}", e);
+            }
+        });
+    }
+
+    /// Stop every worker, later checkouts fail
+    pub async fn shutdown(&self) {
+        let workers: Vec<_> = {
+            let mut workers = self.workers.lock().await;
+            self.closed.store(true, Ordering::SeqCst);
+            workers.drain(..).collect()
+        };
+        let closing = workers.iter().map(|worker| worker.transport.close());
+        for result in futures::future::join_all(closing).await {
+            if let Err(e) = result {
+                warn!("Failed to stop worker of server {}: {:?}", self.server, e);
+            }
+        }
+    }
+
+    pub async fn status(&self) -> PoolStatus {
+        let workers = self.workers.lock().await;
+        PoolStatus {
+            workers: workers.len(),
+            in_flight: workers
+                .iter()
+                .map(|w| w.in_flight.load(Ordering::SeqCst))
+                .collect(),
+            min: self.config.min,
+            max: self.max(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdio::StdioTransport;
+    use async_mcp::client::ClientBuilder;
+    use async_mcp::protocol::RequestOptions;
+
+    /// A worker that reads requests without answering, and exits on `exit`
+    const WORKER: &str = r#"
+while IFS= read -r line; do
+  case "$line" in *'"method":"exit"'*) exit 0 ;; esac
+done
+"#;
+
+    /// A pool of `WORKER`s, with the number of workers started so far
+    fn pool(
+        min: usize,
+        max: usize,
+        idle_secs: u64,
+    ) -> (Arc<WorkerPool<StdioTransport>>, Arc<AtomicUsize>) {
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = started.clone();
+        let connect: Connector<StdioTransport> = Box::new(move |env_vars| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                let args = ["-c".to_string(), WORKER.to_string()];
+                let transport = StdioTransport::new("sh", &args, env_vars);
+                transport.open().await?;
+                Ok((ClientBuilder::new(transport.clone()).build(), transport))
+            })
+        });
+        let config = PoolConfig {
+            min,
+            max,
+            idle_secs,
+        };
+        (
+            Arc::new(WorkerPool::new("test", config, connect, None)),
+            started,
+        )
+    }
+
+    async fn checkout(pool: &Arc<WorkerPool<StdioTransport>>) -> Lease<StdioTransport> {
+        pool.checkout(None).await.unwrap()
+    }
+
+    async fn in_flight(pool: &WorkerPool<StdioTransport>) -> Vec<usize> {
+        pool.status().await.in_flight
+    }
+
+    async fn wait_for_workers(pool: &WorkerPool<StdioTransport>, count: usize) {
+        for _ in 0..300 {
+            if pool.status().await.workers == count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("pool did not reach {} workers", count);
+    }
+
+    #[tokio::test]
+    async fn scales_up_to_max_while_workers_are_busy() {
+        let (pool, started) = pool(1, 3, 300);
+
+        let leases = futures::future::join_all((0..5).map(|_| checkout(&pool))).await;
+        assert_eq!(pool.status().await.workers, 3);
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+        let mut busy = in_flight(&pool).await;
+        busy.sort();
+        assert_eq!(busy, [1, 2, 2]);
+
+        drop(leases);
+        assert_eq!(in_flight(&pool).await, [0, 0, 0]);
+        pool.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn picks_the_least_busy_worker() {
+        let (pool, started) = pool(2, 2, 300);
+
+        let _first = checkout(&pool).await;
+        let second = checkout(&pool).await;
+        let third = checkout(&pool).await;
+        assert_eq!(in_flight(&pool).await, [2, 1]);
+        let _fourth = checkout(&pool).await;
+        assert_eq!(in_flight(&pool).await, [2, 2]);
+        drop((second, third));
+        let _fifth = checkout(&pool).await;
+        assert_eq!(in_flight(&pool).await, [1, 2]);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        pool.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn replaces_workers_that_exited() {
+        let (pool, started) = pool(1, 1, 300);
+
+        let lease = checkout(&pool).await;
+        let options = RequestOptions::default().timeout(Duration::from_millis(100));
+        let _ = lease.request("exit", None, options).await;
+        drop(lease);
+        wait_for_workers(&pool, 0).await;
+
+        let _lease = checkout(&pool).await;
+        assert_eq!(pool.status().await.workers, 1);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        pool.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn idle_workers_are_reaped_down_to_min() {
+        let (pool, _) = pool(1, 3, 1);
+
+        let leases = futures::future::join_all((0..3).map(|_| checkout(&pool))).await;
+        assert_eq!(pool.status().await.workers, 3);
+        drop(leases);
+        wait_for_workers(&pool, 1).await;
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(pool.status().await.workers, 1);
+        pool.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn stop_idle_keeps_min_workers_and_busy_ones() {
+        let (pool, _) = pool(1, 3, 300);
+
+        let mut leases = futures::future::join_all((0..3).map(|_| checkout(&pool))).await;
+        leases.truncate(2);
+        pool.stop_idle(Duration::ZERO).await;
+        assert_eq!(pool.status().await.workers, 2);
+        drop(leases);
+        pool.stop_idle(Duration::ZERO).await;
+        assert_eq!(pool.status().await.workers, 1);
+        pool.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn checkouts_fail_after_shutdown() {
+        let (pool, started) = pool(1, 1, 300);
+        let _ = checkout(&pool).await;
+
+        pool.shutdown().await;
+        assert_eq!(pool.status().await.workers, 0);
+        let error = pool.checkout(None).await.err().unwrap();
+        assert!(error.is::<ProxyError>());
+        assert_eq!(error.to_string(), "Pool for server test is shut down");
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+    }
+}
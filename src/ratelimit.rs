@@ -0,0 +1,314 @@
+//! Token bucket rate limits on tool calls.
+//!
+//! A call draws one token from every bucket that applies to it: the global
+//! bucket, the client's, the server's and the tool's. It is only let through
+//! when all of them have a token, so a rejected call uses up none.
+use async_mcp::types::{CallToolResponse, ToolResponseContent};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::types::RateLimit;
+
+/// What a rate limit applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Global,
+    Identity,
+    Server,
+    Tool,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Global => write!(f, "global"),
+            Scope::Identity => write!(f, "identity"),
+            Scope::Server => write!(f, "server"),
+            Scope::Tool => write!(f, "tool"),
+        }
+    }
+}
+
+/// A limit a call is subject to, `name` tells buckets of the same scope apart
+pub struct Bucket {
+    pub scope: Scope,
+    pub name: String,
+    pub limit: RateLimit,
+}
+
+/// A call refused because a bucket is empty
+#[derive(Debug)]
+pub struct RateLimited {
+    pub scope: Scope,
+    pub name: String,
+    pub limit: RateLimit,
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil() as u64
+    }
+
+    /// The tool error returned to the client, with the details in `_meta`
+    pub fn response(&self) -> CallToolResponse {
+        CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: self.to_string(),
+            }],
+            is_error: Some(true),
+            meta: Some(json!({
+                "rateLimit": {
+                    "scope": self.scope.to_string(),
+                    "name": self.name,
+                    "requests": self.limit.requests,
+                    "periodSecs": self.limit.period_secs,
+                    "retryAfterSecs": self.retry_after_secs(),
+                }
+            })),
+        }
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = match self.scope {
+            Scope::Global => "Global rate limit".to_string(),
+            scope => format!("Rate limit of {} {}", scope, self.name),
+        };
+        write!(
+            f,
+            "{} of {} calls per {}s exceeded, retry in {}s",
+            limit,
+            self.limit.requests,
+            self.limit.period_secs,
+            self.retry_after_secs()
+        )
+    }
+}
+
+/// A bucket holding up to `requests` tokens, refilled evenly over the period
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.requests),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Tokens added per second
+    fn rate(&self) -> f64 {
+        f64::from(self.limit.requests) / self.limit.period_secs.max(1) as f64
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate()).min(f64::from(self.limit.requests));
+        self.updated = now;
+    }
+
+    /// How long until a token is available, none if one is
+    fn wait(&self) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        if self.limit.requests == 0 {
+            return Some(Duration::from_secs(self.limit.period_secs));
+        }
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate()))
+    }
+}
+
+/// Buckets by scope and name, created on first use
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(Scope, String), TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Take a token from every bucket, or from none and report the one that
+    /// keeps the call waiting longest. A bucket starts over when its limit
+    /// changes.
+    pub fn check(&self, limits: &[Bucket]) -> Result<(), RateLimited> {
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut refused: Option<RateLimited> = None;
+        for bucket in limits {
+            let state = buckets
+                .entry((bucket.scope, bucket.name.clone()))
+                .or_insert_with(|| TokenBucket::new(bucket.limit));
+            if state.limit != bucket.limit {
+                *state = TokenBucket::new(bucket.limit);
+            }
+            state.refill(now);
+            if let Some(retry_after) = state.wait() {
+                if refused
+                    .as_ref()
+                    .is_none_or(|refused| retry_after > refused.retry_after)
+                {
+                    refused = Some(RateLimited {
+                        scope: bucket.scope,
+                        name: bucket.name.clone(),
+                        limit: bucket.limit,
+                        retry_after,
+                    });
+                }
+            }
+        }
+        if let Some(refused) = refused {
+            return Err(refused);
+        }
+        for bucket in limits {
+            if let Some(state) = buckets.get_mut(&(bucket.scope, bucket.name.clone())) {
+                state.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(scope: Scope, name: &str, requests: u32, period_secs: u64) -> Bucket {
+        Bucket {
+            scope,
+            name: name.to_string(),
+            limit: RateLimit {
+                requests,
+                period_secs,
+            },
+        }
+    }
+
+    /// Pretend `secs` passed since every bucket was last refilled
+    fn elapse(limiter: &RateLimiter, secs: f64) {
+        let mut buckets = limiter.buckets.lock().unwrap();
+        for state in buckets.values_mut() {
+            state.updated = state
+                .updated
+                .checked_sub(Duration::from_secs_f64(secs))
+                .unwrap();
+        }
+    }
+
+    fn tokens(limiter: &RateLimiter, scope: Scope, name: &str) -> f64 {
+        limiter.buckets.lock().unwrap()[&(scope, name.to_string())].tokens
+    }
+
+    #[test]
+    fn bursts_up_to_the_limit_are_allowed() {
+        let limiter = RateLimiter::default();
+        let limits = [bucket(Scope::Server, "files", 3, 60)];
+        for _ in 0..3 {
+            assert!(limiter.check(&limits).is_ok());
+        }
+        let refused = limiter.check(&limits).unwrap_err();
+        assert_eq!(
+            (refused.scope, refused.name.as_str()),
+            (Scope::Server, "files")
+        );
+        // One token comes back every 20s
+        assert!(refused.retry_after > Duration::from_secs(19));
+        assert!(refused.retry_after <= Duration::from_secs(20));
+        assert_eq!(
+            refused.to_string(),
+            "Rate limit of server files of 3 calls per 60s exceeded, retry in 20s"
+        );
+    }
+
+    #[test]
+    fn buckets_refill_over_the_period() {
+        let limiter = RateLimiter::default();
+        let limits = [bucket(Scope::Tool, "files/read", 2, 10)];
+        assert!(limiter.check(&limits).is_ok());
+        assert!(limiter.check(&limits).is_ok());
+        assert!(limiter.check(&limits).is_err());
+
+        elapse(&limiter, 5.0);
+        assert!(limiter.check(&limits).is_ok());
+        assert!(limiter.check(&limits).is_err());
+
+        // Refill stops at the burst size
+        elapse(&limiter, 3600.0);
+        assert!(limiter.check(&limits).is_ok());
+        assert!(limiter.check(&limits).is_ok());
+        assert!(limiter.check(&limits).is_err());
+    }
+
+    #[test]
+    fn refused_calls_take_no_tokens() {
+        let limiter = RateLimiter::default();
+        let limits = [
+            bucket(Scope::Global, "", 100, 60),
+            bucket(Scope::Identity, "alice", 1, 60),
+        ];
+        assert!(limiter.check(&limits).is_ok());
+        for _ in 0..5 {
+            assert_eq!(limiter.check(&limits).unwrap_err().scope, Scope::Identity);
+        }
+        assert!((tokens(&limiter, Scope::Global, "") - 99.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn the_longest_wait_is_reported() {
+        let limiter = RateLimiter::default();
+        let limits = [
+            bucket(Scope::Server, "files", 1, 10),
+            bucket(Scope::Tool, "files/read", 1, 60),
+        ];
+        assert!(limiter.check(&limits).is_ok());
+        let refused = limiter.check(&limits).unwrap_err();
+        assert_eq!(refused.scope, Scope::Tool);
+        assert_eq!(
+            refused.response().meta.unwrap()["rateLimit"]["retryAfterSecs"],
+            60
+        );
+    }
+
+    #[test]
+    fn buckets_are_kept_per_name() {
+        let limiter = RateLimiter::default();
+        assert!(limiter
+            .check(&[bucket(Scope::Identity, "alice", 1, 60)])
+            .is_ok());
+        assert!(limiter
+            .check(&[bucket(Scope::Identity, "alice", 1, 60)])
+            .is_err());
+        assert!(limiter
+            .check(&[bucket(Scope::Identity, "bob", 1, 60)])
+            .is_ok());
+    }
+
+    #[test]
+    fn changing_a_limit_starts_the_bucket_over() {
+        let limiter = RateLimiter::default();
+        assert!(limiter.check(&[bucket(Scope::Global, "", 1, 60)]).is_ok());
+        assert!(limiter.check(&[bucket(Scope::Global, "", 1, 60)]).is_err());
+        assert!(limiter.check(&[bucket(Scope::Global, "", 2, 60)]).is_ok());
+        assert!(limiter.check(&[bucket(Scope::Global, "", 2, 60)]).is_ok());
+        assert!(limiter.check(&[bucket(Scope::Global, "", 2, 60)]).is_err());
+    }
+
+    #[test]
+    fn a_zero_limit_refuses_every_call() {
+        let limiter = RateLimiter::default();
+        let refused = limiter
+            .check(&[bucket(Scope::Tool, "shell/exec", 0, 30)])
+            .unwrap_err();
+        assert_eq!(refused.retry_after, Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,219 @@
+//! Retries with exponential backoff for transient upstream failures.
+use rand::Rng;
+use std::fmt;
+use std::time::Duration;
+
+use crate::http::StatusError;
+use crate::types::{RetryConfig, RetryableError};
+
+/// A failure of the proxy itself rather than of an upstream server
+#[derive(Debug)]
+pub struct ProxyError(pub String);
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+/// A request to an upstream server that failed
+#[derive(Debug)]
+pub enum UpstreamError {
+    /// No response within the request timeout
+    Timeout(Duration),
+    /// The server answered with a JSON-RPC error
+    Rejected(String),
+    /// The connection or child process failed
+    Transport(String),
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::Timeout(timeout) => {
+                write!(f, "Request timed out after {}s", timeout.as_secs_f64())
+            }
+            UpstreamError::Rejected(message) | UpstreamError::Transport(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+/// Type an error raised while talking to an upstream server.
+///
+/// `async_mcp` reports JSON-RPC errors only as a "Request failed" message.
+/// Anything else failing in its protocol layer or in a transport is a
+/// transport failure, unless it is typed already or the proxy itself failed.
+pub fn upstream_error(error: anyhow::Error) -> anyhow::Error {
+    if classify(&error).is_some() || error.chain().any(|cause| cause.is::<ProxyError>()) {
+        return error;
+    }
+    let message = format!("{:#}", error);
+    if message.starts_with("Request failed") {
+        UpstreamError::Rejected(message).into()
+    } else {
+        UpstreamError::Transport(message).into()
+    }
+}
+
+/// Classify an error returned while calling an upstream tool.
+///
+/// Only failures of the upstream server or the connection to it have a class.
+/// Everything else, the proxy's own refusals in particular, is never retried
+/// and says nothing about the server's health.
+pub fn classify(error: &anyhow::Error) -> Option<RetryableError> {
+    error.chain().find_map(|cause| {
+        if let Some(error) = cause.downcast_ref::<UpstreamError>() {
+            return Some(match error {
+                UpstreamError::Timeout(_) => RetryableError::Timeout,
+                UpstreamError::Rejected(_) => RetryableError::Upstream,
+                UpstreamError::Transport(_) => RetryableError::Transport,
+            });
+        }
+        // The server answered, only session expiry is a lost connection
+        cause
+            .is::<StatusError>()
+            .then_some(RetryableError::Upstream)
+    })
+}
+
+/// Whether an error shows the server or the connection to it is failing
+pub fn is_failure(error: &anyhow::Error) -> bool {
+    matches!(
+        classify(error),
+        Some(RetryableError::Timeout | RetryableError::Transport)
+    )
+}
+
+/// Delay before the attempt following `attempt` (1-based)
+pub fn backoff(config: &RetryConfig, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1) as i32;
+    let delay = (config.initial_backoff_ms as f64 * config.multiplier.powi(exponent))
+        .min(config.max_backoff_ms as f64) as u64;
+    if config.jitter && delay > 1 {
+        // Equal jitter: keep half of the delay and randomize the rest
+        let half = delay / 2;
+        Duration::from_millis(half + rand::thread_rng().gen_range(0..=delay - half))
+    } else {
+        Duration::from_millis(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use reqwest::StatusCode;
+
+    fn config(jitter: bool) -> RetryConfig {
+        RetryConfig {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+            multiplier: 2.0,
+            jitter,
+            retry_on: vec![RetryableError::Timeout, RetryableError::Transport],
+            idempotent_only: true,
+        }
+    }
+
+    #[test]
+    fn proxy_errors_are_not_classified() {
+        let error = anyhow::Error::new(ProxyError("Proxy is shutting down".to_string()));
+        assert_eq!(classify(&error), None);
+        assert_eq!(classify(&error.context("calling tool")), None);
+        assert!(!is_failure(&anyhow::Error::new(ProxyError("busy".into()))));
+
+        let parse = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(classify(&anyhow::Error::new(parse)), None);
+    }
+
+    #[test]
+    fn untyped_errors_are_not_classified() {
+        for message in ["Request timed out", "Request failed: oops", "Broken pipe"] {
+            assert_eq!(classify(&anyhow!(message)), None, "{}", message);
+        }
+    }
+
+    #[test]
+    fn http_status_errors_are_upstream_errors() {
+        let error = anyhow::Error::new(StatusError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            body: "oops".to_string(),
+        });
+        assert_eq!(classify(&error), Some(RetryableError::Upstream));
+        assert!(!is_failure(&error));
+    }
+
+    #[test]
+    fn upstream_errors_are_classified() {
+        let timeout = anyhow::Error::new(UpstreamError::Timeout(Duration::from_secs(5)));
+        assert_eq!(classify(&timeout), Some(RetryableError::Timeout));
+        assert!(is_failure(&timeout));
+        assert_eq!(timeout.to_string(), "Request timed out after 5s");
+
+        let transport = anyhow::Error::new(UpstreamError::Transport("Broken pipe".into()));
+        assert_eq!(
+            classify(&transport.context("Failed to initialize server files")),
+            Some(RetryableError::Transport)
+        );
+        let rejected = anyhow::Error::new(UpstreamError::Rejected("Request failed".into()));
+        assert_eq!(classify(&rejected), Some(RetryableError::Upstream));
+        assert!(!is_failure(&rejected));
+    }
+
+    #[test]
+    fn errors_from_an_upstream_are_typed() {
+        assert_eq!(
+            classify(&upstream_error(anyhow!(
+                "Request failed: Some(invalid params)"
+            ))),
+            Some(RetryableError::Upstream)
+        );
+        assert_eq!(
+            classify(&upstream_error(anyhow!("Broken pipe").context("sending"))),
+            Some(RetryableError::Transport)
+        );
+        let proxy = upstream_error(ProxyError("No token".to_string()).into());
+        assert_eq!(classify(&proxy), None);
+        let timeout = upstream_error(UpstreamError::Timeout(Duration::from_secs(1)).into());
+        assert_eq!(classify(&timeout), Some(RetryableError::Timeout));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_cap() {
+        let config = config(false);
+        let delays: Vec<u64> = (1..=6)
+            .map(|attempt| backoff(&config, attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+    }
+
+    #[test]
+    fn jitter_keeps_at_least_half_of_the_delay() {
+        let config = config(true);
+        for attempt in 1..=6 {
+            let full = backoff(
+                &RetryConfig {
+                    jitter: false,
+                    ..config.clone()
+                },
+                attempt,
+            );
+            for _ in 0..20 {
+                let delay = backoff(&config, attempt);
+                assert!(
+                    delay >= full / 2 && delay <= full,
+                    "{:?} vs {:?}",
+                    delay,
+                    full
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Meta-tools for discovering tools on demand in large catalogs.
+//!
+//! When `tool_search` is enabled, `tools/list` only exposes these tools and the
+//! full catalog is reached through `search_tools`, `describe_tool` and `call_tool`.
+use async_mcp::types::Tool;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+pub const SEARCH_TOOLS: &str = "search_tools";
+pub const DESCRIBE_TOOL: &str = "describe_tool";
+pub const CALL_TOOL: &str = "call_tool";
+
+const DEFAULT_LIMIT: usize = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchArgs {
+    #[serde(default)]
+    pub query: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DescribeArgs {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallArgs {
+    pub name: String,
+    pub arguments: Option<HashMap<String, Value>>,
+}
+
+pub fn is_meta_tool(name: &str) -> bool {
+    matches!(name, SEARCH_TOOLS | DESCRIBE_TOOL | CALL_TOOL)
+}
+
+/// The tools exposed by `tools/list` in search mode
+pub fn meta_tools() -> Vec<Tool> {
+    vec![
+        Tool {
+            name: SEARCH_TOOLS.to_string(),
+            description: Some(
+                "Search the available tools by keywords matched against tool names and descriptions. Returns matching tool names with their descriptions.".to_string(),
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Space separated keywords" },
+                    "limit": { "type": "integer", "description": "Maximum number of results", "default": DEFAULT_LIMIT }
+                },
+                "required": ["query"]
+            }),
+            output_schema: None,
+        },
+        Tool {
+            name: DESCRIBE_TOOL.to_string(),
+            description: Some(
+                "Get the full definition of a tool, including its input schema.".to_string(),
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "description": "Tool name as returned by search_tools" }
+                },
+                "required": ["name"]
+            }),
+            output_schema: None,
+        },
+        Tool {
+            name: CALL_TOOL.to_string(),
+            description: Some("Call a tool by name with the given arguments.".to_string()),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "description": "Tool name as returned by search_tools" },
+                    "arguments": { "type": "object", "description": "Arguments matching the tool's input schema" }
+                },
+                "required": ["name"]
+            }),
+            output_schema: None,
+        },
+    ]
+}
+
+/// Rank tools by how many query keywords appear in their name and description.
+///
+/// Name matches weigh more than description matches. An empty query returns the
+/// first `limit` tools.
+pub fn search<'a>(tools: &'a [Tool], args: &SearchArgs) -> Vec<&'a Tool> {
+    let limit = args.limit.unwrap_or(DEFAULT_LIMIT);
+    let terms: Vec<String> = args
+        .query
+        .split_whitespace()
+        .map(|t| t.to_lowercase())
+        .collect();
+
+    let mut scored: Vec<(usize, &Tool)> = tools
+        .iter()
+        .filter_map(|tool| {
+            if terms.is_empty() {
+                return Some((0, tool));
+            }
+            let name = tool.name.to_lowercase();
+            let description = tool
+                .description
+                .as_deref()
+                .unwrap_or_default()
+                .to_lowercase();
+            let score: usize = terms
+                .iter()
+                .map(|term| {
+                    let mut score = 0;
+                    if name.contains(term.as_str()) {
+                        score += 2;
+                    }
+                    if description.contains(term.as_str()) {
+                        score += 1;
+                    }
+                    score
+                })
+                .sum();
+            (score > 0).then_some((score, tool))
+        })
+        .collect();
+
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.name.cmp(&b.name)));
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, tool)| tool)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(name: &str, description: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: Some(description.to_string()),
+            input_schema: json!({ "type": "object" }),
+            output_schema: None,
+        }
+    }
+
+    fn names(tools: &[&Tool]) -> Vec<String> {
+        tools.iter().map(|tool| tool.name.clone()).collect()
+    }
+
+    fn args(query: &str, limit: Option<usize>) -> SearchArgs {
+        SearchArgs {
+            query: query.to_string(),
+            limit,
+        }
+    }
+
+    fn catalog() -> Vec<Tool> {
+        vec![
+            tool("read_file", "Read a file from disk"),
+            tool("write_file", "Write a file to disk"),
+            tool("fetch", "Fetch a URL and return it as markdown"),
+            tool("file_search", "Find files by name"),
+        ]
+    }
+
+    #[test]
+    fn name_matches_rank_above_description_matches() {
+        let tools = catalog();
+        let found = search(&tools, &args("fetch markdown", None));
+        assert_eq!(names(&found), ["fetch"]);
+
+        let found = search(&tools, &args("file", None));
+        assert_eq!(names(&found), ["file_search", "read_file", "write_file"]);
+
+        let found = search(&tools, &args("disk", None));
+        assert_eq!(names(&found), ["read_file", "write_file"]);
+    }
+
+    #[test]
+    fn matching_is_case_insensitive() {
+        let tools = catalog();
+        assert_eq!(names(&search(&tools, &args("FETCH", None))), ["fetch"]);
+    }
+
+    #[test]
+    fn empty_query_lists_tools_up_to_the_limit() {
+        let tools = catalog();
+        let found = search(&tools, &args("  ", Some(2)));
+        assert_eq!(names(&found), ["fetch", "file_search"]);
+    }
+
+    #[test]
+    fn unmatched_queries_find_nothing() {
+        let tools = catalog();
+        assert!(search(&tools, &args("database", None)).is_empty());
+    }
+
+    #[test]
+    fn meta_tools_are_recognized() {
+        assert!(meta_tools().iter().all(|tool| is_meta_tool(&tool.name)));
+        assert!(!is_meta_tool("read_file"));
+    }
+}
@@ -1,36 +1,61 @@
 #![allow(dead_code)]
 use anyhow::Result;
 use async_mcp::{
-    client::ClientBuilder,
+    client::{Client, ClientBuilder},
     protocol::RequestOptions,
     server::Server,
     transport::{
-        ClientSseTransport, ClientStdioTransport, ClientWsTransport, ClientWsTransportBuilder,
+        ClientSseTransport, ClientWsTransport, ClientWsTransportBuilder, JsonRpcNotification,
         Message, Transport,
     },
     types::{
-        CallToolRequest, CallToolResponse, ListRequest, ResourcesListResponse, ServerCapabilities,
-        Tool, ToolResponseContent, ToolsListResponse,
+        CallToolRequest, CallToolResponse, ListRequest, ReadResourceRequest, ResourcesListResponse,
+        ServerCapabilities, Tool, ToolResponseContent, ToolsListResponse, LATEST_PROTOCOL_VERSION,
     },
 };
+use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
-use std::path::Path;
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::fs::read_to_string;
-use tokio::sync::Mutex;
-use tracing::{debug, error, info};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex};
+use tracing::{debug, error, info, warn};
 
-use crate::types::{ProxyMcpServer, ProxyMcpServerType, ProxyServerConfig as Config};
+use crate::approval::{ApprovalManager, ApprovalOutcome};
+use crate::auth::Identity;
+use crate::balancer::{Balancer, EndpointStatus};
+use crate::breaker::{BreakerStatus, CircuitBreaker};
+use crate::cache::{self, CacheStats, ResultCache};
+use crate::composite;
+use crate::credentials::Credentials;
+use crate::http::StreamableHttpTransport;
+use crate::limiter::{ConcurrencyLimiter, LimiterStatus};
+use crate::output::{self, OutputOwner, OutputStore, ReadResourceResponse};
+use crate::policy::{self, Access, ToolDecision};
+use crate::pool::{Connector, Lease, PoolStatus, WorkerPool};
+use crate::ratelimit::{Bucket, RateLimiter, Scope};
+use crate::retry::{self, ProxyError, UpstreamError};
+use crate::search;
+use crate::stdio::StdioTransport;
+use crate::supervisor::{RestartStatus, Supervisor};
+use crate::types::{
+    CompositeToolConfig, InstanceConfig, KeepaliveConfig, OutputLimitConfig, ProxyMcpServer,
+    ProxyMcpServerType, ProxyServerConfig as Config, RestartConfig, RetryConfig, RetryableError,
+    StartMode, ToolAnnotations, UpstreamAuth,
+};
 
 // Update the type to use an enum
 #[derive(Clone)]
+#[allow(clippy::upper_case_acronyms)]
 enum ClientTransport {
     SSE(ClientSseTransport),
-    Stdio(ClientStdioTransport),
+    Stdio(StdioTransport),
     WS(ClientWsTransport),
+    Http(StreamableHttpTransport),
 }
 
 const TOOL_SEPARATOR: &str = "---";
@@ -41,6 +66,7 @@ impl Transport for ClientTransport {
             ClientTransport::SSE(t) => t.send(message).await,
             ClientTransport::Stdio(t) => t.send(message).await,
             ClientTransport::WS(t) => t.send(message).await,
+            ClientTransport::Http(t) => t.send(message).await,
         }
     }
 
@@ -49,6 +75,7 @@ impl Transport for ClientTransport {
             ClientTransport::SSE(t) => t.receive().await,
             ClientTransport::Stdio(t) => t.receive().await,
             ClientTransport::WS(t) => t.receive().await,
+            ClientTransport::Http(t) => t.receive().await,
         }
     }
 
@@ -57,6 +84,7 @@ impl Transport for ClientTransport {
             ClientTransport::SSE(t) => t.close().await,
             ClientTransport::Stdio(t) => t.close().await,
             ClientTransport::WS(t) => t.close().await,
+            ClientTransport::Http(t) => t.close().await,
         }
     }
     async fn open(&self) -> Result<()> {
@@ -64,6 +92,7 @@ impl Transport for ClientTransport {
             ClientTransport::SSE(t) => t.open().await?,
             ClientTransport::Stdio(t) => t.open().await?,
             ClientTransport::WS(t) => t.open().await?,
+            ClientTransport::Http(t) => t.open().await?,
         }
         Ok(())
     }
@@ -72,262 +101,1300 @@ impl Transport for ClientTransport {
 #[derive(Clone)]
 pub struct McpProxy {
     config: Arc<Config>,
-    clients: Arc<Mutex<HashMap<String, async_mcp::client::Client<ClientTransport>>>>,
+    clients: Arc<Mutex<HashMap<String, Connection>>>,
     tools_cache: Arc<Mutex<HashMap<String, Vec<Tool>>>>,
     resources_cache: Arc<Mutex<HashMap<String, Vec<async_mcp::types::Resource>>>>,
+    outputs: Arc<Mutex<OutputStore>>,
+    annotations_cache: Arc<Mutex<HashMap<String, HashMap<String, ToolAnnotations>>>>,
+    result_cache: Option<Arc<Mutex<ResultCache>>>,
+    approvals: Arc<ApprovalManager>,
+    balancers: Arc<HashMap<String, Balancer>>,
+    breakers: Arc<HashMap<String, CircuitBreaker>>,
+    limiters: Arc<HashMap<String, ConcurrencyLimiter>>,
+    pools: Arc<HashMap<String, Arc<WorkerPool<ClientTransport>>>>,
+    supervisor: Arc<Supervisor>,
+    env_hasher: RandomState,
+    /// Set by `shutdown`, no connections are opened afterwards
+    stopping: Arc<AtomicBool>,
+    /// The client this proxy serves, checked against the authorization rules
+    identity: Arc<Identity>,
+    /// Lazy servers whose catalog was not fetched from them yet
+    unlisted: Arc<std::sync::Mutex<HashSet<String>>>,
+    rate_limiter: Arc<RateLimiter>,
+    /// Upstream credentials by client key
+    credentials: Arc<HashMap<String, Arc<Credentials>>>,
+    /// Held while connecting to a client key, so each key connects once at a
+    /// time without blocking calls to other servers
+    connecting: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+/// A shared client connected to an upstream server
+#[derive(Clone)]
+struct Connection {
+    client: Client<ClientTransport>,
+    transport: ClientTransport,
+    /// Environment variables supplied by the caller this instance belongs to
+    env_vars: Option<HashMap<String, String>>,
+    /// Set once the connection is lost, failing requests still waiting on it
+    lost: Arc<watch::Sender<bool>>,
+    in_flight: Arc<AtomicUsize>,
+    last_used: Arc<std::sync::Mutex<Instant>>,
+}
+
+impl Connection {
+    fn is(&self, other: &Connection) -> bool {
+        Arc::ptr_eq(&self.lost, &other.lost)
+    }
+
+    fn touch(&self) {
+        if let Ok(mut last_used) = self.last_used.lock() {
+            *last_used = Instant::now();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            return Duration::ZERO;
+        }
+        self.last_used
+            .lock()
+            .map(|last_used| last_used.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+/// A client used for one request
+enum Upstream {
+    Shared(Connection),
+    Pooled(Lease<ClientTransport>),
+}
+
+impl Upstream {
+    /// Send a request, failing early if the connection is lost meanwhile
+    async fn request(
+        &self,
+        method: &str,
+        params: Option<Value>,
+        timeout: Duration,
+    ) -> Result<Value> {
+        match self {
+            Upstream::Shared(connection) => {
+                let mut lost = connection.lost.subscribe();
+                connection.in_flight.fetch_add(1, Ordering::SeqCst);
+                let result = tokio::select! {
+                    response = send(&connection.client, method, params, timeout) => response,
+                    _ = lost.wait_for(|lost| *lost) => Err(UpstreamError::Transport(
+                        "Connection to the server was lost".to_string(),
+                    )
+                    .into()),
+                };
+                connection.in_flight.fetch_sub(1, Ordering::SeqCst);
+                connection.touch();
+                result
+            }
+            Upstream::Pooled(lease) => send(lease, method, params, timeout).await,
+        }
+    }
+}
+
+/// Send a request to an upstream server, typing the errors it fails with.
+///
+/// `async_mcp` times out with nothing but a message, so the request is given
+/// a little longer there and times out here first.
+async fn send(
+    client: &Client<ClientTransport>,
+    method: &str,
+    params: Option<Value>,
+    timeout: Duration,
+) -> Result<Value> {
+    let options = RequestOptions::default().timeout(timeout + Duration::from_secs(1));
+    match tokio::time::timeout(timeout, client.request(method, params, options)).await {
+        Ok(response) => response.map_err(retry::upstream_error),
+        Err(_) => Err(UpstreamError::Timeout(timeout).into()),
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct McpCache {
     tools: HashMap<String, Vec<Tool>>,
     resources: HashMap<String, Vec<async_mcp::types::Resource>>,
+    #[serde(default)]
+    annotations: HashMap<String, HashMap<String, ToolAnnotations>>,
+}
+
+/// Read the catalogs saved to `catalog_file`, if there are any
+fn load_catalog(config: &Config) -> Option<McpCache> {
+    let path = config.catalog_file.as_ref()?;
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read catalog file {}: {}", path, e);
+            return None;
+        }
+    };
+    match serde_json::from_str(&content) {
+        Ok(catalog) => Some(catalog),
+        Err(e) => {
+            warn!("Ignoring invalid catalog file {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// The tools of a server configured with an `input_schema`
+fn static_catalog(server: &ProxyMcpServer) -> (Vec<Tool>, HashMap<String, ToolAnnotations>) {
+    let mut tools = Vec::new();
+    let mut annotations = HashMap::new();
+    for (name, tool) in &server.tools {
+        let Some(input_schema) = &tool.input_schema else {
+            continue;
+        };
+        tools.push(Tool {
+            name: name.clone(),
+            description: tool.description.clone(),
+            input_schema: input_schema.clone(),
+            output_schema: None,
+        });
+        if let Some(tool_annotations) = &tool.annotations {
+            annotations.insert(name.clone(), tool_annotations.clone());
+        }
+    }
+    tools.sort_by(|a, b| a.name.cmp(&b.name));
+    (tools, annotations)
+}
+
+/// Collect the `annotations` of each tool in a raw `tools/list` result.
+///
+/// `async_mcp::types::Tool` does not model annotations, so they are read from
+/// the JSON before it is parsed.
+fn tool_annotations(value: &Value) -> HashMap<String, ToolAnnotations> {
+    value
+        .get("tools")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|tool| {
+            let name = tool.get("name")?.as_str()?;
+            let annotations = serde_json::from_value(tool.get("annotations")?.clone()).ok()?;
+            Some((name.to_string(), annotations))
+        })
+        .collect()
+}
+
+/// Set a key in the `_meta` object of a tool response
+fn set_meta(response: &mut CallToolResponse, key: &str, value: Value) {
+    match response.meta.as_mut() {
+        Some(Value::Object(meta)) => {
+            meta.insert(key.to_string(), value);
+        }
+        _ => response.meta = Some(json!({ key: value })),
+    }
+}
+
+fn config_cache(config: &Config) -> Option<Arc<Mutex<ResultCache>>> {
+    config
+        .cache
+        .as_ref()
+        .map(|cache| Arc::new(Mutex::new(ResultCache::new(cache))))
+}
+
+fn config_balancers(config: &Config) -> HashMap<String, Balancer> {
+    config
+        .servers
+        .iter()
+        .filter_map(|(name, server)| match &server.server_type {
+            ProxyMcpServerType::Group {
+                endpoints,
+                strategy,
+                health,
+            } => Some((
+                name.clone(),
+                Balancer::new(name, *strategy, health.clone(), endpoints.len()),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+fn config_breakers(config: &Config) -> HashMap<String, CircuitBreaker> {
+    config
+        .servers
+        .iter()
+        .filter_map(|(name, server)| {
+            let breaker = server
+                .circuit_breaker
+                .as_ref()
+                .or(config.circuit_breaker.as_ref())?;
+            Some((name.clone(), CircuitBreaker::new(name, breaker.clone())))
+        })
+        .collect()
+}
+
+fn config_limiters(config: &Config) -> HashMap<String, ConcurrencyLimiter> {
+    config
+        .servers
+        .iter()
+        .filter_map(|(name, server)| {
+            let concurrency = server.concurrency.clone()?;
+            Some((name.clone(), ConcurrencyLimiter::new(name, concurrency)))
+        })
+        .collect()
+}
+
+/// The credentials of every upstream with an `auth` block, by client key
+fn config_credentials(config: &Config) -> HashMap<String, Arc<Credentials>> {
+    fn auth(server_type: &ProxyMcpServerType) -> Option<&UpstreamAuth> {
+        match server_type {
+            ProxyMcpServerType::SSE { auth, .. }
+            | ProxyMcpServerType::WS { auth, .. }
+            | ProxyMcpServerType::Http { auth, .. } => auth.as_ref(),
+            _ => None,
+        }
+    }
+
+    let timeout = Duration::from_secs(config.timeout.connect);
+    let mut credentials = HashMap::new();
+    for (name, server) in &config.servers {
+        for (client_key, server_type) in endpoints(name, server) {
+            if let Some(auth) = auth(server_type) {
+                let source = Credentials::new(&client_key, auth.clone(), timeout);
+                credentials.insert(client_key, Arc::new(source));
+            }
+        }
+    }
+    credentials
+}
+
+/// Open a transport to an upstream server and build a client on it.
+///
+/// The caller is responsible for spawning the client's `start` loop.
+async fn connect(
+    client_key: &str,
+    server_type: &ProxyMcpServerType,
+    env_vars: Option<HashMap<String, String>>,
+    credentials: Option<Arc<Credentials>>,
+    timeout: Duration,
+) -> Result<(Client<ClientTransport>, ClientTransport)> {
+    // SSE and WebSocket headers are fixed once connected
+    let authorization = match (server_type, &credentials) {
+        (ProxyMcpServerType::SSE { .. } | ProxyMcpServerType::WS { .. }, Some(credentials)) => {
+            Some(credentials.authorization().await?)
+        }
+        _ => None,
+    };
+    let transport = match server_type {
+        ProxyMcpServerType::SSE { url, headers, .. } => {
+            let mut transport = ClientSseTransport::builder(url.clone());
+            if let Some(authorization) = &authorization {
+                transport = transport.with_header("Authorization", authorization);
+            }
+            let transport = match headers {
+                Some(headers) => {
+                    for (key, value) in headers.iter() {
+                        transport = transport.with_header(key, value);
+                    }
+                    transport
+                }
+                None => transport,
+            }
+            .build();
+
+            ClientTransport::SSE(transport)
+        }
+        ProxyMcpServerType::Stdio {
+            command,
+            args,
+            env_vars: default_env_vars,
+        } => {
+            let env_vars = match env_vars {
+                Some(env_vars) => Some(env_vars),
+                None => default_env_vars.clone(),
+            };
+            ClientTransport::Stdio(StdioTransport::new(command, args, env_vars))
+        }
+        ProxyMcpServerType::WS { url, headers, .. } => {
+            let mut transport = ClientWsTransportBuilder::new(url.clone());
+            if let Some(authorization) = &authorization {
+                transport = transport.with_header("Authorization", authorization);
+            }
+            let transport = match headers {
+                Some(headers) => {
+                    for (key, value) in headers.iter() {
+                        transport = transport.with_header(key, value);
+                    }
+                    transport
+                }
+                None => transport,
+            }
+            .build();
+            ClientTransport::WS(transport)
+        }
+        ProxyMcpServerType::Http { url, headers, .. } => ClientTransport::Http(
+            StreamableHttpTransport::new(url, headers.clone(), credentials, timeout),
+        ),
+        ProxyMcpServerType::Group { .. } => {
+            return Err(ProxyError(format!(
+                "Endpoint {} of a group cannot be a group",
+                client_key
+            ))
+            .into())
+        }
+    };
+
+    tokio::time::timeout(timeout, transport.open())
+        .await
+        .map_err(|_| {
+            UpstreamError::Transport(format!(
+                "Timed out connecting to server {} after {}s",
+                client_key,
+                timeout.as_secs()
+            ))
+        })?
+        .map_err(retry::upstream_error)?;
+
+    let client = ClientBuilder::new(transport.clone()).build();
+    Ok((client, transport))
+}
+
+/// Start the session with the initialize handshake, which SSE and WebSocket
+/// servers expect on every new connection, reconnects included
+async fn handshake(
+    client: &Client<ClientTransport>,
+    transport: &ClientTransport,
+    timeout: Duration,
+) -> Result<()> {
+    let params = json!({
+        "protocolVersion": LATEST_PROTOCOL_VERSION,
+        "capabilities": {},
+        "clientInfo": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    });
+    send(client, "initialize", Some(params), timeout).await?;
+    transport
+        .send(&Message::Notification(JsonRpcNotification {
+            method: "notifications/initialized".to_string(),
+            ..Default::default()
+        }))
+        .await
+        .map_err(retry::upstream_error)
+}
+
+fn config_pools(config: &Config) -> HashMap<String, Arc<WorkerPool<ClientTransport>>> {
+    let mut pools = HashMap::new();
+    for (name, server) in &config.servers {
+        let Some(pool) = &server.pool else {
+            continue;
+        };
+        if !matches!(server.server_type, ProxyMcpServerType::Stdio { .. }) {
+            warn!(
+                "Ignoring pool for server {}, only stdio servers can be pooled",
+                name
+            );
+            continue;
+        }
+        let client_key = name.clone();
+        let server_type = server.server_type.clone();
+        let timeout = Duration::from_secs(config.timeout.connect);
+        let connector: Connector<ClientTransport> = Box::new(move |env_vars| {
+            let client_key = client_key.clone();
+            let server_type = server_type.clone();
+            Box::pin(
+                async move { connect(&client_key, &server_type, env_vars, None, timeout).await },
+            )
+        });
+        pools.insert(
+            name.clone(),
+            Arc::new(WorkerPool::new(
+                name,
+                pool.clone(),
+                connector,
+                server
+                    .idle_timeout
+                    .map(|secs| Duration::from_secs(secs.max(1))),
+            )),
+        );
+    }
+    pools
+}
+
+/// Key of the client connected to one endpoint of a group
+fn endpoint_key(server_name: &str, index: usize) -> String {
+    format!("{}#{}", server_name, index)
+}
+
+/// The clients to connect for a server: one per endpoint for groups
+fn endpoints<'a>(
+    server_name: &str,
+    server: &'a ProxyMcpServer,
+) -> Vec<(String, &'a ProxyMcpServerType)> {
+    match &server.server_type {
+        ProxyMcpServerType::Group { endpoints, .. } => endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| (endpoint_key(server_name, index), endpoint))
+            .collect(),
+        server_type => vec![(server_name.to_string(), server_type)],
+    }
+}
+
+/// The client key a per-caller instance was started for, `server` or
+/// `server#endpoint`
+fn base_key(client_key: &str) -> &str {
+    client_key.split('@').next().unwrap_or(client_key)
+}
+
+/// The server a client key belongs to.
+///
+/// Keys are `server`, `server#endpoint` for group endpoints and
+/// `...@envhash` for instances started with a caller's environment.
+fn server_name(client_key: &str) -> &str {
+    client_key.split(['#', '@']).next().unwrap_or(client_key)
 }
 
 impl McpProxy {
     /// Initialize the proxy's caches from a file path or a JSON string
     pub fn new(config: Arc<Config>, cached_content: &str) -> Result<McpProxy> {
-        let cache_data: McpCache = serde_json::from_str(&cached_content)?;
+        let cache_data: McpCache = serde_json::from_str(cached_content)?;
 
         // Update the tools cache
-        let proxy = McpProxy {
-            config,
-            clients: Arc::new(Mutex::new(HashMap::new())),
-            tools_cache: Arc::new(Mutex::new(cache_data.tools)),
-            resources_cache: Arc::new(Mutex::new(cache_data.resources)),
-        };
-
-        Ok(proxy)
+        Ok(Self::with_cache(config, cache_data))
     }
 
     pub async fn initialize(config: Arc<Config>) -> Result<Self> {
         info!("Creating new MCP Proxy");
-        let proxy = Self {
+        let proxy = Self::with_cache(
             config,
-            clients: Arc::new(Mutex::new(HashMap::new())),
-            tools_cache: Arc::new(Mutex::new(HashMap::new())),
-            resources_cache: Arc::new(Mutex::new(HashMap::new())),
-        };
+            McpCache {
+                tools: HashMap::new(),
+                resources: HashMap::new(),
+                annotations: HashMap::new(),
+            },
+        );
 
         // Initialize caches for all servers
-        proxy.init_caches().await?;
+        let catalog = load_catalog(&proxy.config);
+        proxy.init_caches(catalog).await?;
 
         Ok(proxy)
     }
 
-    async fn get_or_create_client(
+    fn with_cache(config: Arc<Config>, cache_data: McpCache) -> Self {
+        Self {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            connecting: Arc::new(Mutex::new(HashMap::new())),
+            tools_cache: Arc::new(Mutex::new(cache_data.tools)),
+            resources_cache: Arc::new(Mutex::new(cache_data.resources)),
+            outputs: Arc::new(Mutex::new(OutputStore::default())),
+            annotations_cache: Arc::new(Mutex::new(cache_data.annotations)),
+            result_cache: config_cache(&config),
+            approvals: Arc::new(ApprovalManager::new(config.approval.clone())),
+            balancers: Arc::new(config_balancers(&config)),
+            breakers: Arc::new(config_breakers(&config)),
+            limiters: Arc::new(config_limiters(&config)),
+            pools: Arc::new(config_pools(&config)),
+            supervisor: Arc::new(Supervisor::default()),
+            env_hasher: RandomState::new(),
+            stopping: Arc::new(AtomicBool::new(false)),
+            identity: Arc::new(Identity::default()),
+            unlisted: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            credentials: Arc::new(config_credentials(&config)),
+            config,
+        }
+    }
+
+    /// A handle to the same proxy that serves the given client
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+
+    /// The rate limits a call to a tool is subject to
+    fn rate_limits(
         &self,
         server_name: &str,
         server: &ProxyMcpServer,
+        tool_name: &str,
+    ) -> Vec<Bucket> {
+        let mut limits = Vec::new();
+        if let Some(limit) = self.config.rate_limits.global {
+            limits.push(Bucket {
+                scope: Scope::Global,
+                name: String::new(),
+                limit,
+            });
+        }
+        // Clients without a subject share the global limit only
+        if let Some(subject) = &self.identity.subject {
+            let limit = self
+                .identity
+                .key
+                .as_ref()
+                .and_then(|key| key.rate_limit)
+                .or(self.config.rate_limits.per_identity);
+            if let Some(limit) = limit {
+                limits.push(Bucket {
+                    scope: Scope::Identity,
+                    name: subject.clone(),
+                    limit,
+                });
+            }
+        }
+        if let Some(limit) = server.rate_limit {
+            limits.push(Bucket {
+                scope: Scope::Server,
+                name: server_name.to_string(),
+                limit,
+            });
+        }
+        if let Some(limit) = server.tools.get(tool_name).and_then(|tool| tool.rate_limit) {
+            limits.push(Bucket {
+                scope: Scope::Tool,
+                name: format!("{}{TOOL_SEPARATOR}{}", server_name, tool_name),
+                limit,
+            });
+        }
+        limits
+    }
+
+    fn access(&self) -> Access<'_> {
+        Access::new(self.config.authorization.as_ref(), &self.identity)
+    }
+
+    async fn get_or_create_client(
+        &self,
+        client_key: &str,
+        server_type: &ProxyMcpServerType,
         env_vars: Option<HashMap<String, String>>,
-    ) -> Result<async_mcp::client::Client<ClientTransport>> {
-        let mut clients = self.clients.lock().await;
+    ) -> Result<Connection> {
+        if let Some(connection) = self.current_client(client_key, &env_vars).await? {
+            return Ok(connection);
+        }
 
-        if let Some(client) = clients.get(server_name) {
-            return Ok(client.clone());
+        let key_lock = self
+            .connecting
+            .lock()
+            .await
+            .entry(client_key.to_string())
+            .or_default()
+            .clone();
+        let result = {
+            let _connecting = key_lock.lock().await;
+            self.create_client(client_key, server_type, env_vars).await
+        };
+        let mut connecting = self.connecting.lock().await;
+        // Only this call and the map still refer to the lock
+        if Arc::strong_count(&key_lock) == 2 {
+            connecting.remove(client_key);
         }
+        result
+    }
 
-        let transport = match &server.server_type {
-            ProxyMcpServerType::SSE { url, headers } => {
-                let mut transport = ClientSseTransport::builder(url.clone());
-                let transport = match headers {
-                    Some(headers) => {
-                        for (key, value) in headers.iter() {
-                            transport = transport.with_header(key, value);
-                        }
-                        transport
-                    }
-                    None => transport,
-                }
-                .build();
-
-                ClientTransport::SSE(transport)
-            }
-            ProxyMcpServerType::Stdio {
-                command,
-                args,
-                env_vars: default_env_vars,
-            } => {
-                let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-                let env_vars = match env_vars {
-                    Some(env_vars) => Some(env_vars),
-                    None => default_env_vars.clone(),
-                };
-                let transport = ClientStdioTransport::new(command.as_str(), &args, env_vars)?;
-                ClientTransport::Stdio(transport)
-            }
-            ProxyMcpServerType::WS { url, headers } => {
-                let mut transport = ClientWsTransportBuilder::new(url.clone());
-                let transport = match headers {
-                    Some(headers) => {
-                        for (key, value) in headers.iter() {
-                            transport = transport.with_header(key, value);
-                        }
-                        transport
-                    }
-                    None => transport,
+    /// The connection to a client key, if there is one
+    async fn current_client(
+        &self,
+        client_key: &str,
+        env_vars: &Option<HashMap<String, String>>,
+    ) -> Result<Option<Connection>> {
+        let clients = self.clients.lock().await;
+        let Some(connection) = clients.get(client_key) else {
+            return Ok(None);
+        };
+        // Never let one caller's instance serve another caller's environment
+        if connection.env_vars != *env_vars {
+            return Err(ProxyError(format!(
+                "Instance {} belongs to a different caller",
+                client_key
+            ))
+            .into());
+        }
+        connection.touch();
+        Ok(Some(connection.clone()))
+    }
+
+    /// Connect to a client key while holding its lock in `connecting`. The
+    /// `clients` lock is not held meanwhile.
+    async fn create_client(
+        &self,
+        client_key: &str,
+        server_type: &ProxyMcpServerType,
+        env_vars: Option<HashMap<String, String>>,
+    ) -> Result<Connection> {
+        // Another call may have connected while this one waited
+        if let Some(connection) = self.current_client(client_key, &env_vars).await? {
+            return Ok(connection);
+        }
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(ProxyError("Proxy is shutting down".to_string()).into());
+        }
+        self.supervisor
+            .check(client_key, self.restart_config(client_key))?;
+        if env_vars.is_some() {
+            self.evict_instance(&mut *self.clients.lock().await, client_key)?;
+        }
+        // Per-caller instances use the credentials of the server they belong to
+        let credentials = self.credentials.get(base_key(client_key)).cloned();
+        let timeout = Duration::from_secs(self.config.timeout.connect);
+        let (client, transport) = connect(
+            client_key,
+            server_type,
+            env_vars.clone(),
+            credentials.clone(),
+            timeout,
+        )
+        .await?;
+        let connection = Connection {
+            client: client.clone(),
+            transport: transport.clone(),
+            env_vars: env_vars.clone(),
+            lost: Arc::new(watch::channel(false).0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            last_used: Arc::new(std::sync::Mutex::new(Instant::now())),
+        };
+
+        let proxy = self.clone();
+        let key = client_key.to_string();
+        let (lost_connection, lost_server_type, lost_env_vars) =
+            (connection.clone(), server_type.clone(), env_vars.clone());
+        tokio::spawn(async move {
+            let result = client.start().await;
+            // Only a stdio child ending its output is a reliable sign that the
+            // server is gone, the SSE and WebSocket transports never end.
+            let reason = match (&transport, result) {
+                (_, Err(e)) => e.to_string(),
+                (ClientTransport::Stdio(stdio), Ok(())) => match stdio.exit_status() {
+                    Some(status) => status.to_string(),
+                    None => "closed its stdout".to_string(),
+                },
+                _ => "connection closed".to_string(),
+            };
+            proxy
+                .connection_lost(
+                    &key,
+                    &lost_connection,
+                    &lost_server_type,
+                    lost_env_vars,
+                    reason,
+                )
+                .await;
+        });
+
+        if let ClientTransport::SSE(_) | ClientTransport::WS(_) = &connection.transport {
+            let timeout = Duration::from_secs(self.config.timeout.connect);
+            if let Err(e) = handshake(&connection.client, &connection.transport, timeout).await {
+                connection.lost.send_replace(true);
+                if let Err(e) = connection.transport.close().await {
+                    warn!("Failed to close connection to {}: {:?}", client_key, e);
                 }
-                .build();
-                ClientTransport::WS(transport)
+                return Err(e.context(format!("Failed to initialize server {}", client_key)));
             }
-        };
+        }
 
-        match &transport {
-            ClientTransport::SSE(t) => t.open().await?,
-            ClientTransport::Stdio(t) => t.open().await?,
-            ClientTransport::WS(t) => t.open().await?,
+        {
+            let mut clients = self.clients.lock().await;
+            // Checked under the lock, a connection that ends from now on is
+            // found by `connection_lost`
+            let failure = if self.stopping.load(Ordering::SeqCst) {
+                Some(ProxyError("Proxy is shutting down".to_string()).into())
+            } else if *connection.lost.borrow() {
+                Some(
+                    UpstreamError::Transport(format!(
+                        "Connection to server {} closed while connecting",
+                        client_key
+                    ))
+                    .into(),
+                )
+            } else {
+                None
+            };
+            if let Some(failure) = failure {
+                drop(clients);
+                connection.lost.send_replace(true);
+                if let Err(e) = connection.transport.close().await {
+                    warn!("Failed to close connection to {}: {:?}", client_key, e);
+                }
+                return Err(failure);
+            }
+            clients.insert(client_key.to_string(), connection.clone());
         }
 
-        let client = ClientBuilder::new(transport).build();
-        let client_clone = client.clone();
+        let server = self.config.servers.get(server_name(client_key));
+        let idle_timeout = match &env_vars {
+            Some(_) => Some(self.instance_config(client_key).idle_secs),
+            None => server.and_then(|server| server.idle_timeout),
+        };
+        if let Some(idle_timeout) = idle_timeout {
+            tokio::spawn(self.clone().stop_when_idle(
+                client_key.to_string(),
+                connection.clone(),
+                Duration::from_secs(idle_timeout.max(1)),
+            ));
+        }
+        if let Some(keepalive) = server.and_then(|server| server.keepalive.clone()) {
+            tokio::spawn(self.clone().keepalive(
+                client_key.to_string(),
+                connection.clone(),
+                server_type.clone(),
+                env_vars.clone(),
+                keepalive,
+            ));
+        }
+        // Streamable HTTP sends the current token with every request instead
+        if let (Some(credentials), ClientTransport::SSE(_) | ClientTransport::WS(_)) =
+            (credentials, &connection.transport)
+        {
+            tokio::spawn(self.clone().renew_credentials(
+                client_key.to_string(),
+                connection.clone(),
+                server_type.clone(),
+                env_vars,
+                credentials,
+            ));
+        }
+        Ok(connection)
+    }
 
-        tokio::spawn(async move { client_clone.start().await });
+    /// Drop a lost connection, fail the requests waiting on it and reconnect.
+    ///
+    /// Only marks the connection lost if it was already replaced or is still
+    /// being set up.
+    async fn connection_lost(
+        &self,
+        client_key: &str,
+        connection: &Connection,
+        server_type: &ProxyMcpServerType,
+        env_vars: Option<HashMap<String, String>>,
+        reason: String,
+    ) {
+        {
+            let mut clients = self.clients.lock().await;
+            if !clients
+                .get(client_key)
+                .is_some_and(|current| current.is(connection))
+            {
+                connection.lost.send_replace(true);
+                return;
+            }
+            clients.remove(client_key);
+        }
+        connection.lost.send_replace(true);
+        if connection.env_vars.is_some() {
+            // Per-caller instances are started again by their caller's next call
+            warn!("Instance {} stopped: {}", client_key, reason);
+            return;
+        }
+        tokio::spawn(self.clone().restart(
+            client_key.to_string(),
+            server_type.clone(),
+            env_vars,
+            reason,
+        ));
+    }
 
-        clients.insert(server_name.to_string(), client.clone());
-        Ok(client)
+    /// The instance limits of the server a client belongs to
+    fn instance_config(&self, client_key: &str) -> &InstanceConfig {
+        self.config
+            .servers
+            .get(server_name(client_key))
+            .and_then(|server| server.instances.as_ref())
+            .unwrap_or(&self.config.instances)
     }
 
-    pub async fn build<T: Transport>(self, t: T) -> Result<Server<T>> {
-        let proxy = Arc::new(self);
+    /// Make room for a new per-caller instance of a server by stopping the
+    /// least recently used idle one once `max_instances` are running
+    fn evict_instance(
+        &self,
+        clients: &mut HashMap<String, Connection>,
+        client_key: &str,
+    ) -> Result<()> {
+        let base = base_key(client_key);
+        let prefix = format!("{}@", base);
+        let max_instances = self.instance_config(client_key).max_instances;
+        let instances: Vec<(&String, Duration)> = clients
+            .iter()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, connection)| (key, connection.idle_for()))
+            .collect();
+        if instances.len() < max_instances {
+            return Ok(());
+        }
+        let Some(lru) = instances
+            .into_iter()
+            .filter(|(_, idle_for)| !idle_for.is_zero())
+            .max_by_key(|(_, idle_for)| *idle_for)
+            .map(|(key, _)| key.clone())
+        else {
+            return Err(ProxyError(format!(
+                "Server {} is busy: all {} per-caller instances are in use",
+                base, max_instances
+            ))
+            .into());
+        };
 
-        let server = Server::builder(t)
-            .capabilities(ServerCapabilities::default())
-            .request_handler("resources/list", {
-                let proxy = proxy.clone();
-                move |_req: ListRequest| {
-                    let proxy = proxy.clone();
-                    Box::pin(async move { Ok(proxy.aggregate_resources().await) })
-                }
-            })
-            .request_handler("tools/list", {
-                let proxy = proxy.clone();
-                move |_req: ListRequest| {
-                    let proxy = proxy.clone();
-                    Box::pin(async move { Ok(proxy.aggregate_tools().await) })
-                }
-            })
-            .request_handler("tools/call", {
-                let proxy = proxy.clone();
-                move |req: CallToolRequest| {
-                    let proxy = proxy.clone();
-                    Box::pin(async move {
-                        match proxy.handle_tool(req).await {
-                            Ok(response) => Ok(response),
-                            Err(e) => Ok(CallToolResponse {
-                                content: vec![ToolResponseContent::Text {
-                                    text: e.to_string(),
-                                }],
-                                is_error: Some(true),
-                                meta: None,
-                            }),
-                        }
-                    })
+        info!("Evicting least recently used instance {}", lru);
+        if let Some(connection) = clients.remove(&lru) {
+            connection.lost.send_replace(true);
+            tokio::spawn(async move {
+                if let Err(e) = connection.transport.close().await {
+                    warn!("Failed to close evicted instance: {:?}", e);
                 }
             });
-
-        Ok(server.build())
+        }
+        Ok(())
     }
 
-    async fn init_caches(&self) -> Result<()> {
-        info!("Initializing caches for all servers");
-        let mut tool_futures = Vec::new();
-        let mut resource_futures = Vec::new();
-
-        // Create futures for all servers
-        for (name, server) in &self.config.servers {
-            info!("Setting up server: {}", name);
-            let name = name.clone();
-            let server = server.clone();
-            let self_clone = self;
-
-            let name_clone = name.clone();
-            let server_clone = server.clone();
+    /// Running per-caller instances of every server
+    pub async fn instance_counts(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for (key, connection) in self.clients.lock().await.iter() {
+            if connection.env_vars.is_some() {
+                *counts.entry(server_name(key).to_string()).or_default() += 1;
+            }
+        }
+        counts
+    }
 
-            // Future for fetching tools
-            let tools_future = async move {
-                debug!("Fetching tools for server: {}", name);
-                let client = match self_clone.get_or_create_client(&name, &server, None).await {
-                    Ok(client) => client,
-                    Err(e) => {
-                        error!("Failed to connect to server {}: {:?}", name, e);
-                        return Ok((name, Vec::new())); // Return empty tools on error
-                    }
+    /// Reconnect to a server that stopped, with backoff and a restart budget.
+    ///
+    /// Boxed because the new connection spawns tasks that may restart it again.
+    fn restart(
+        self,
+        client_key: String,
+        server_type: ProxyMcpServerType,
+        env_vars: Option<HashMap<String, String>>,
+        mut reason: String,
+    ) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            loop {
+                warn!("Server {} stopped: {}", client_key, reason);
+                let config = self.restart_config(&client_key);
+                let Some(delay) = self.supervisor.record_exit(&client_key, &reason, config) else {
+                    error!(
+                        "Server {} stopped {} times within {}s, not restarting it",
+                        client_key, config.max_restarts, config.window_secs
+                    );
+                    return;
                 };
-
-                debug!("Sending tools/list request to {}", name);
-                let response = client
-                    .request(
-                        "tools/list",
-                        None,
-                        RequestOptions::default()
-                            .timeout(Duration::from_secs(self.config.timeout.list)),
-                    )
-                    .await?;
-
-                // Parse JSON-RPC response
-                debug!("tools/list response  {response}");
-                match serde_json::from_value::<serde_json::Value>(response) {
-                    Ok(value) => {
-                        let tools_response: ToolsListResponse = serde_json::from_value(value)?;
-                        info!(
-                            "Successfully fetched {} tools from {}",
-                            tools_response.tools.len(),
-                            name
-                        );
-                        Ok((name, tools_response.tools))
-                    }
-                    Err(e) => {
-                        error!("Failed to parse tools response from {}: {:?}", name, e);
-                        Ok((name, Vec::new()))
-                    }
+                tokio::time::sleep(delay).await;
+                if self.clients.lock().await.contains_key(&client_key) {
+                    // Already reconnected by a call
+                    return;
                 }
-            };
-            tool_futures.push(tools_future);
 
-            // Future for fetching resources
-            let resources_future = async move {
-                debug!("Fetching resources for server: {}", name_clone);
-                let client = match self_clone
-                    .get_or_create_client(&name_clone, &server_clone, None)
+                info!("Restarting server {}", client_key);
+                match self
+                    .get_or_create_client(&client_key, &server_type, env_vars.clone())
                     .await
                 {
-                    Ok(client) => client,
-                    Err(e) => {
-                        error!("Failed to connect to server {}: {:?}", name_clone, e);
-                        return Ok((name_clone, Vec::new())); // Return empty resources on error
+                    Ok(_) => {
+                        self.refresh_tools(server_name(&client_key)).await;
+                        return;
                     }
-                };
-
-                debug!("Sending resources/list request to {}", name_clone);
-                let server_resources = match client
-                    .request(
-                        "resources/list",
-                        None,
-                        RequestOptions::default()
-                            .timeout(Duration::from_secs(self.config.timeout.list)),
-                    )
-                    .await
+                    Err(e) => reason = e.to_string(),
+                }
+            }
+        })
+    }
+
+    /// Stop a connection once it has been idle for `idle_timeout`, the next
+    /// call starts it again
+    async fn stop_when_idle(
+        self,
+        client_key: String,
+        connection: Connection,
+        idle_timeout: Duration,
+    ) {
+        loop {
+            let idle_for = connection.idle_for();
+            if idle_for < idle_timeout {
+                tokio::time::sleep(idle_timeout - idle_for).await;
+                continue;
+            }
+            if *connection.lost.borrow() {
+                return;
+            }
+            if self
+                .disconnect(&client_key, &connection, idle_timeout)
+                .await
+            {
+                info!(
+                    "Stopped server {} after {}s without calls",
+                    client_key,
+                    idle_timeout.as_secs()
+                );
+                return;
+            }
+        }
+    }
+
+    /// Close a connection idle for at least `min_idle` without restarting it.
+    ///
+    /// Returns false if the connection is in use or was already replaced.
+    async fn disconnect(
+        &self,
+        client_key: &str,
+        connection: &Connection,
+        min_idle: Duration,
+    ) -> bool {
+        {
+            let mut clients = self.clients.lock().await;
+            // Checked under the lock so a call cannot pick the connection up meanwhile
+            if connection.idle_for() < min_idle
+                || !clients
+                    .get(client_key)
+                    .is_some_and(|current| current.is(connection))
+            {
+                return false;
+            }
+            clients.remove(client_key);
+        }
+        connection.lost.send_replace(true);
+        if let Err(e) = connection.transport.close().await {
+            warn!("Failed to close connection to {}: {:?}", client_key, e);
+        }
+        true
+    }
+
+    /// Close every upstream connection and pooled worker, stopping stdio
+    /// servers, before the proxy exits
+    pub async fn shutdown(&self) {
+        let connections: Vec<_> = {
+            let mut clients = self.clients.lock().await;
+            self.stopping.store(true, Ordering::SeqCst);
+            clients.drain().collect()
+        };
+        info!("Stopping {} upstream connections", connections.len());
+        let closing = connections.into_iter().map(|(key, connection)| async move {
+            connection.lost.send_replace(true);
+            if let Err(e) = connection.transport.close().await {
+                warn!("Failed to close connection to {}: {:?}", key, e);
+            }
+        });
+        futures::future::join_all(closing).await;
+        futures::future::join_all(self.pools.values().map(|pool| pool.shutdown())).await;
+    }
+
+    /// Reconnect with a new token before the connection's token expires.
+    ///
+    /// New calls go to the new connection, the old one is closed once the
+    /// calls still running on it are done. Boxed because the new connection
+    /// renews its token in turn.
+    fn renew_credentials(
+        self,
+        client_key: String,
+        connection: Connection,
+        server_type: ProxyMcpServerType,
+        env_vars: Option<HashMap<String, String>>,
+        credentials: Arc<Credentials>,
+    ) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            let Some(refresh_at) = credentials.refresh_at().await else {
+                return;
+            };
+            let mut lost = connection.lost.subscribe();
+            tokio::select! {
+                _ = tokio::time::sleep_until(refresh_at.into()) => {}
+                _ = lost.wait_for(|lost| *lost) => return,
+            }
+            {
+                let mut clients = self.clients.lock().await;
+                if !clients
+                    .get(&client_key)
+                    .is_some_and(|current| current.is(&connection))
                 {
-                    Ok(response) => match serde_json::from_value::<ResourcesListResponse>(response)
-                    {
-                        Ok(resources) => resources,
-                        Err(e) => {
-                            error!("Invalid resources response from {}: {:?}", name_clone, e);
-                            return Ok((name_clone, Vec::new())); // Return empty resources on parse error
+                    return;
+                }
+                clients.remove(&client_key);
+            }
+
+            info!("Reconnecting to {} with a new token", client_key);
+            if let Err(e) = self
+                .get_or_create_client(&client_key, &server_type, env_vars.clone())
+                .await
+            {
+                // Hand over to the restart policy, as if the connection was lost
+                tokio::spawn(self.clone().restart(
+                    client_key.clone(),
+                    server_type,
+                    env_vars,
+                    e.to_string(),
+                ));
+            }
+            while connection.in_flight.load(Ordering::SeqCst) > 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            connection.lost.send_replace(true);
+            if let Err(e) = connection.transport.close().await {
+                warn!("Failed to close connection to {}: {:?}", client_key, e);
+            }
+        })
+    }
+
+    /// Ping a server periodically and reconnect when it stops answering
+    async fn keepalive(
+        self,
+        client_key: String,
+        connection: Connection,
+        server_type: ProxyMcpServerType,
+        env_vars: Option<HashMap<String, String>>,
+        config: KeepaliveConfig,
+    ) {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if *connection.lost.borrow() {
+                return;
+            }
+            let ping = send(
+                &connection.client,
+                "ping",
+                None,
+                Duration::from_secs(config.timeout_secs),
+            );
+            match ping.await {
+                // An error response still shows the server is there
+                Err(e) if retry::is_failure(&e) => {
+                    self.connection_lost(
+                        &client_key,
+                        &connection,
+                        &server_type,
+                        env_vars,
+                        format!("keepalive ping failed: {}", e),
+                    )
+                    .await;
+                    return;
+                }
+                _ => debug!("Keepalive ping to {} succeeded", client_key),
+            }
+        }
+    }
+
+    /// The restart policy of the server a client belongs to
+    fn restart_config(&self, client_key: &str) -> &RestartConfig {
+        self.config
+            .servers
+            .get(server_name(client_key))
+            .and_then(|server| server.restart.as_ref())
+            .unwrap_or(&self.config.restart)
+    }
+
+    /// Fetch the tool list of a server again after it was restarted
+    async fn refresh_tools(&self, server_name: &str) {
+        let Some(server) = self.config.servers.get(server_name) else {
+            return;
+        };
+        match self.fetch_server_tools(server_name, server).await {
+            Ok((tools, annotations)) => {
+                info!("Server {}: Refreshed {} tools", server_name, tools.len());
+                self.tools_cache
+                    .lock()
+                    .await
+                    .insert(server_name.to_string(), tools);
+                self.annotations_cache
+                    .lock()
+                    .await
+                    .insert(server_name.to_string(), annotations);
+            }
+            Err(e) => error!("Failed to refresh tools of {}: {:?}", server_name, e),
+        }
+    }
+
+    /// Save the fetched catalogs to `catalog_file`, leaving out lazy servers
+    /// that were not listed yet
+    async fn save_catalog(&self) {
+        let Some(path) = &self.config.catalog_file else {
+            return;
+        };
+        let result = async {
+            let mut catalog = self.state().await?;
+            for name in self.unlisted.lock().unwrap().iter() {
+                catalog.tools.remove(name);
+                catalog.resources.remove(name);
+                catalog.annotations.remove(name);
+            }
+            std::fs::write(path, serde_json::to_string(&catalog)?)?;
+            anyhow::Ok(())
+        };
+        if let Err(e) = result.await {
+            warn!("Failed to save catalog file {}: {:?}", path, e);
+        }
+    }
+
+    /// Fetch the catalog of a lazy server the first time it runs
+    fn list_started(&self, server_name: &str) {
+        if !self.unlisted.lock().unwrap().remove(server_name) {
+            return;
+        }
+        let proxy = self.clone();
+        let server_name = server_name.to_string();
+        tokio::spawn(async move {
+            proxy.refresh_tools(&server_name).await;
+            let resources = match proxy.config.servers.get(&server_name) {
+                Some(server) => proxy.fetch_server_resources(&server_name, server).await,
+                None => return,
+            };
+            proxy
+                .resources_cache
+                .lock()
+                .await
+                .insert(server_name, resources);
+            proxy.save_catalog().await;
+        });
+    }
+
+    /// Restart counts and last exit of every server that stopped
+    pub fn restart_status(&self) -> HashMap<String, RestartStatus> {
+        self.supervisor.status()
+    }
+
+    /// A client for one request, checked out of the worker pool for pooled servers
+    async fn checkout(
+        &self,
+        client_key: &str,
+        server_type: &ProxyMcpServerType,
+        env_vars: Option<HashMap<String, String>>,
+    ) -> Result<Upstream> {
+        match (self.pools.get(client_key), &env_vars) {
+            (Some(pool), None) => Ok(Upstream::Pooled(pool.checkout(None).await?)),
+            // Callers with their own environment get their own instance
+            (_, Some(env)) => {
+                let instance_key = format!("{}@{:016x}", client_key, self.env_hash(env));
+                Ok(Upstream::Shared(
+                    self.get_or_create_client(&instance_key, server_type, env_vars)
+                        .await?,
+                ))
+            }
+            (None, None) => Ok(Upstream::Shared(
+                self.get_or_create_client(client_key, server_type, None)
+                    .await?,
+            )),
+        }
+    }
+
+    /// Hash of a caller's environment, keyed per process so it reveals nothing
+    fn env_hash(&self, env_vars: &HashMap<String, String>) -> u64 {
+        let mut pairs: Vec<_> = env_vars.iter().collect();
+        pairs.sort();
+        self.env_hasher.hash_one(&pairs)
+    }
+
+    /// Worker pool status of every pooled server
+    pub async fn pool_status(&self) -> HashMap<String, PoolStatus> {
+        let mut status = HashMap::new();
+        for (name, pool) in self.pools.iter() {
+            status.insert(name.clone(), pool.status().await);
+        }
+        status
+    }
+
+    pub async fn build<T: Transport>(self, t: T) -> Result<Server<T>> {
+        let proxy = Arc::new(self);
+
+        let server = Server::builder(t)
+            .capabilities(ServerCapabilities::default())
+            .request_handler("resources/list", {
+                let proxy = proxy.clone();
+                move |_req: ListRequest| {
+                    let proxy = proxy.clone();
+                    Box::pin(async move { Ok(proxy.aggregate_resources().await) })
+                }
+            })
+            .request_handler("resources/read", {
+                let proxy = proxy.clone();
+                move |req: ReadResourceRequest| {
+                    let proxy = proxy.clone();
+                    Box::pin(async move { proxy.read_resource(req).await })
+                }
+            })
+            .request_handler("tools/list", {
+                let proxy = proxy.clone();
+                move |_req: ListRequest| {
+                    let proxy = proxy.clone();
+                    Box::pin(async move { Ok(proxy.aggregate_tools().await) })
+                }
+            })
+            .request_handler("tools/call", {
+                let proxy = proxy.clone();
+                move |req: CallToolRequest| {
+                    let proxy = proxy.clone();
+                    Box::pin(async move {
+                        match proxy.handle_tool(req).await {
+                            Ok(response) => Ok(response),
+                            Err(e) => Ok(CallToolResponse {
+                                content: vec![ToolResponseContent::Text {
+                                    text: e.to_string(),
+                                }],
+                                is_error: Some(true),
+                                meta: None,
+                            }),
                         }
-                    },
-                    Err(e) => {
-                        error!("Failed to fetch resources from {}: {:?}", name_clone, e);
-                        // Return empty resources on request error
-                        return Ok((name_clone, Vec::new()));
+                    })
+                }
+            });
+
+        Ok(server.build())
+    }
+
+    /// Fetch the catalogs of all eager servers. Lazy servers are not started,
+    /// they are listed from the saved `catalog` or their configured tools.
+    async fn init_caches(&self, mut catalog: Option<McpCache>) -> Result<()> {
+        info!("Initializing caches for all servers");
+        let mut tool_futures = Vec::new();
+        let mut resource_futures = Vec::new();
+        let mut lazy_catalogs = Vec::new();
+
+        // Create futures for all servers
+        for (name, server) in &self.config.servers {
+            if server.start == StartMode::Lazy {
+                let saved = catalog.as_mut().and_then(|catalog| {
+                    let tools = catalog.tools.remove(name)?;
+                    let annotations = catalog.annotations.remove(name).unwrap_or_default();
+                    let resources = catalog.resources.remove(name).unwrap_or_default();
+                    Some((tools, annotations, resources))
+                });
+                let (tools, annotations, resources) = match saved {
+                    Some(saved) => saved,
+                    None => {
+                        self.unlisted.lock().unwrap().insert(name.clone());
+                        let (tools, annotations) = static_catalog(server);
+                        (tools, annotations, Vec::new())
                     }
                 };
-
                 info!(
-                    "Successfully fetched {} resources from {}",
-                    server_resources.resources.len(),
-                    name_clone
+                    "Server {} starts lazily, listing {} tools until the first call",
+                    name,
+                    tools.len()
                 );
-                Ok((name_clone, server_resources.resources))
+                lazy_catalogs.push((name.clone(), tools, annotations, resources));
+                continue;
+            }
+            info!("Setting up server: {}", name);
+            let name = name.clone();
+            let server = server.clone();
+            let self_clone = self;
+
+            let name_clone = name.clone();
+            let server_clone = server.clone();
+
+            // Future for fetching tools
+            let tools_future = async move {
+                debug!("Fetching tools for server: {}", name);
+                let (tools, annotations) = self_clone.fetch_server_tools(&name, &server).await?;
+                Ok((name, tools, annotations))
+            };
+            tool_futures.push(tools_future);
+
+            // Future for fetching resources
+            let resources_future = async move {
+                debug!("Fetching resources for server: {}", name_clone);
+                let resources = self_clone
+                    .fetch_server_resources(&name_clone, &server_clone)
+                    .await;
+                Ok((name_clone, resources))
             };
             resource_futures.push(resources_future);
         }
@@ -355,10 +1422,13 @@ impl McpProxy {
         // Update caches with results
         debug!("Updating tools cache");
         let mut tools_cache = self.tools_cache.lock().await;
+        let mut annotations_cache = self.annotations_cache.lock().await;
         *tools_cache = HashMap::new();
-        for (name, tools) in tools_results {
+        *annotations_cache = HashMap::new();
+        for (name, tools, annotations) in tools_results {
             info!("Server {}: Cached {} tools", name, tools.len());
-            tools_cache.insert(name, tools);
+            tools_cache.insert(name.clone(), tools);
+            annotations_cache.insert(name, annotations);
         }
 
         debug!("Updating resources cache");
@@ -368,18 +1438,165 @@ impl McpProxy {
             info!("Server {}: Cached {} resources", name, resources.len());
             resources_cache.insert(name, resources);
         }
+        for (name, tools, annotations, resources) in lazy_catalogs {
+            tools_cache.insert(name.clone(), tools);
+            annotations_cache.insert(name.clone(), annotations);
+            resources_cache.insert(name, resources);
+        }
+        drop((tools_cache, annotations_cache, resources_cache));
+        self.save_catalog().await;
 
         info!("Successfully initialized all caches");
         Ok(())
     }
 
+    /// Fetch the tool catalog of a server.
+    ///
+    /// The catalogs of all reachable endpoints of a group are merged, keeping
+    /// the first definition of each tool.
+    async fn fetch_server_tools(
+        &self,
+        server_name: &str,
+        server: &ProxyMcpServer,
+    ) -> Result<(Vec<Tool>, HashMap<String, ToolAnnotations>)> {
+        let ProxyMcpServerType::Group { .. } = &server.server_type else {
+            return self.fetch_tools(server_name, &server.server_type).await;
+        };
+
+        let mut tools: Vec<Tool> = Vec::new();
+        let mut annotations = HashMap::new();
+        for (index, (client_key, server_type)) in
+            endpoints(server_name, server).into_iter().enumerate()
+        {
+            let (endpoint_tools, endpoint_annotations) =
+                match self.fetch_tools(&client_key, server_type).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        error!("Failed to fetch tools from {}: {:?}", client_key, e);
+                        if let Some(balancer) = self.balancers.get(server_name) {
+                            balancer.record_failure(index);
+                        }
+                        continue;
+                    }
+                };
+            for tool in endpoint_tools {
+                if !tools.iter().any(|t| t.name == tool.name) {
+                    tools.push(tool);
+                }
+            }
+            for (name, hints) in endpoint_annotations {
+                annotations.entry(name).or_insert(hints);
+            }
+        }
+        Ok((tools, annotations))
+    }
+
+    async fn fetch_tools(
+        &self,
+        client_key: &str,
+        server_type: &ProxyMcpServerType,
+    ) -> Result<(Vec<Tool>, HashMap<String, ToolAnnotations>)> {
+        let client = match self.checkout(client_key, server_type, None).await {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to connect to server {}: {:?}", client_key, e);
+                return Ok((Vec::new(), HashMap::new())); // Return empty tools on error
+            }
+        };
+
+        debug!("Sending tools/list request to {}", client_key);
+        let response = client
+            .request(
+                "tools/list",
+                None,
+                Duration::from_secs(self.config.timeout.list),
+            )
+            .await?;
+
+        // Parse JSON-RPC response
+        debug!("tools/list response  {response}");
+        let annotations = tool_annotations(&response);
+        let tools_response: ToolsListResponse = serde_json::from_value(response)?;
+        info!(
+            "Successfully fetched {} tools from {}",
+            tools_response.tools.len(),
+            client_key
+        );
+        Ok((tools_response.tools, annotations))
+    }
+
+    /// Fetch the resources of a server, from the first endpoint that has any for groups
+    async fn fetch_server_resources(
+        &self,
+        server_name: &str,
+        server: &ProxyMcpServer,
+    ) -> Vec<async_mcp::types::Resource> {
+        for (client_key, server_type) in endpoints(server_name, server) {
+            let resources = self.fetch_resources(&client_key, server_type).await;
+            if !resources.is_empty() {
+                return resources;
+            }
+        }
+        Vec::new()
+    }
+
+    async fn fetch_resources(
+        &self,
+        client_key: &str,
+        server_type: &ProxyMcpServerType,
+    ) -> Vec<async_mcp::types::Resource> {
+        let client = match self.checkout(client_key, server_type, None).await {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to connect to server {}: {:?}", client_key, e);
+                return Vec::new(); // Return empty resources on error
+            }
+        };
+
+        debug!("Sending resources/list request to {}", client_key);
+        let server_resources = match client
+            .request(
+                "resources/list",
+                None,
+                Duration::from_secs(self.config.timeout.list),
+            )
+            .await
+        {
+            Ok(response) => match serde_json::from_value::<ResourcesListResponse>(response) {
+                Ok(resources) => resources,
+                Err(e) => {
+                    error!("Invalid resources response from {}: {:?}", client_key, e);
+                    return Vec::new(); // Return empty resources on parse error
+                }
+            },
+            Err(e) => {
+                error!("Failed to fetch resources from {}: {:?}", client_key, e);
+                // Return empty resources on request error
+                return Vec::new();
+            }
+        };
+
+        info!(
+            "Successfully fetched {} resources from {}",
+            server_resources.resources.len(),
+            client_key
+        );
+        server_resources.resources
+    }
+
     // Rest of the implementation methods...
     async fn aggregate_resources(&self) -> ResourcesListResponse {
         let resources = self.resources_cache.lock().await;
+        let access = self.access();
         let mut all_resources = Vec::new();
 
-        for server_resources in resources.values() {
-            all_resources.extend_from_slice(server_resources);
+        for (server_name, server_resources) in resources.iter() {
+            all_resources.extend(
+                server_resources
+                    .iter()
+                    .filter(|resource| access.allows_resource(server_name, resource.uri.as_str()))
+                    .cloned(),
+            );
         }
 
         ResourcesListResponse {
@@ -390,23 +1607,127 @@ impl McpProxy {
     }
 
     async fn aggregate_tools(&self) -> Value {
+        let all_tools = if self.config.tool_search {
+            search::meta_tools()
+        } else {
+            self.list_tools().await
+        };
+        let response = ToolsListResponse {
+            tools: all_tools,
+            next_cursor: None,
+            meta: None,
+        };
+
+        serde_json::to_value(response).unwrap_or_default()
+    }
+
+    /// The tool catalog the client may use, with server-prefixed names and
+    /// composite tools
+    async fn list_tools(&self) -> Vec<Tool> {
         let tools = self.tools_cache.lock().await;
+        let annotations = self.annotations_cache.lock().await;
+        let access = self.access();
         let mut all_tools = Vec::new();
 
         for (server_name, server_tools) in tools.iter() {
             for tool in server_tools {
+                if !access.allows_tool(server_name, &tool.name)
+                    || !self
+                        .tool_decision(server_name, &tool.name, &annotations)
+                        .is_allowed()
+                {
+                    continue;
+                }
                 let mut tool = tool.clone();
                 tool.name = format!("{}{TOOL_SEPARATOR}{}", server_name, tool.name);
                 all_tools.push(tool);
             }
         }
-        let response = ToolsListResponse {
-            tools: all_tools,
-            next_cursor: None,
-            meta: None,
-        };
+        for (name, composite) in self.config.composite_tools.iter() {
+            let authorized = composite
+                .steps
+                .iter()
+                .all(|step| access.allows_tool(&step.server, &step.tool));
+            if authorized
+                && self
+                    .composite_decision(composite, &annotations)
+                    .is_allowed()
+            {
+                all_tools.push(composite.to_tool(name));
+            }
+        }
+        all_tools
+    }
 
-        serde_json::to_value(response).unwrap_or_default()
+    /// Decide whether a tool of an upstream server may be listed and called
+    fn tool_decision(
+        &self,
+        server_name: &str,
+        tool_name: &str,
+        annotations: &HashMap<String, HashMap<String, ToolAnnotations>>,
+    ) -> ToolDecision {
+        let read_only = self
+            .config
+            .servers
+            .get(server_name)
+            .and_then(|server| server.read_only)
+            .unwrap_or(self.config.read_only);
+        if !read_only {
+            return ToolDecision::Allowed;
+        }
+        policy::read_only_decision(
+            tool_name,
+            annotations
+                .get(server_name)
+                .and_then(|tools| tools.get(tool_name)),
+            &self.config.mutating_patterns,
+        )
+    }
+
+    /// A composite tool is blocked if any of its steps is
+    fn composite_decision(
+        &self,
+        composite: &CompositeToolConfig,
+        annotations: &HashMap<String, HashMap<String, ToolAnnotations>>,
+    ) -> ToolDecision {
+        composite
+            .steps
+            .iter()
+            .map(
+                |step| match self.tool_decision(&step.server, &step.tool, annotations) {
+                    ToolDecision::Allowed => ToolDecision::Allowed,
+                    ToolDecision::Blocked(reason) => ToolDecision::Blocked(format!(
+                        "step {}{TOOL_SEPARATOR}{}: {}",
+                        step.server, step.tool, reason
+                    )),
+                },
+            )
+            .find(|decision| !decision.is_allowed())
+            .unwrap_or(ToolDecision::Allowed)
+    }
+
+    /// The decision for every known tool, sorted by name
+    pub async fn tool_decisions(&self) -> Vec<(String, ToolDecision)> {
+        let tools = self.tools_cache.lock().await;
+        let annotations = self.annotations_cache.lock().await;
+        let mut decisions = Vec::new();
+
+        for (server_name, server_tools) in tools.iter() {
+            for tool in server_tools {
+                decisions.push((
+                    format!("{}{TOOL_SEPARATOR}{}", server_name, tool.name),
+                    self.tool_decision(server_name, &tool.name, &annotations),
+                ));
+            }
+        }
+        for (name, composite) in self.config.composite_tools.iter() {
+            decisions.push((
+                name.clone(),
+                self.composite_decision(composite, &annotations),
+            ));
+        }
+        decisions.sort_by(|a, b| a.0.cmp(&b.0));
+        decisions
     }
 
     fn get_env_vars(req: &CallToolRequest) -> Option<HashMap<String, String>> {
@@ -428,6 +1749,73 @@ impl McpProxy {
     }
 
     async fn handle_tool(&self, req: CallToolRequest) -> Result<CallToolResponse> {
+        if self.config.tool_search && search::is_meta_tool(&req.name) {
+            return self.handle_meta_tool(req).await;
+        }
+        self.dispatch_tool(req).await
+    }
+
+    /// Serve the search mode meta-tools from the cached catalog
+    async fn handle_meta_tool(&self, req: CallToolRequest) -> Result<CallToolResponse> {
+        let arguments = serde_json::to_value(req.arguments.clone().unwrap_or_default())?;
+        let text = match req.name.as_str() {
+            search::SEARCH_TOOLS => {
+                let args: search::SearchArgs = serde_json::from_value(arguments)?;
+                let tools = self.list_tools().await;
+                let results: Vec<Value> = search::search(&tools, &args)
+                    .into_iter()
+                    .map(|tool| json!({ "name": tool.name, "description": tool.description }))
+                    .collect();
+                serde_json::to_string_pretty(&results)?
+            }
+            search::DESCRIBE_TOOL => {
+                let args: search::DescribeArgs = serde_json::from_value(arguments)?;
+                let tools = self.list_tools().await;
+                let tool = tools
+                    .iter()
+                    .find(|tool| tool.name == args.name)
+                    .ok_or_else(|| anyhow::anyhow!("Tool {} not found", args.name))?;
+                serde_json::to_string_pretty(tool)?
+            }
+            search::CALL_TOOL => {
+                let args: search::CallArgs = serde_json::from_value(arguments)?;
+                return self
+                    .dispatch_tool(CallToolRequest {
+                        name: args.name,
+                        arguments: args.arguments,
+                        meta: req.meta,
+                    })
+                    .await;
+            }
+            name => anyhow::bail!("Unknown meta tool {}", name),
+        };
+
+        Ok(CallToolResponse {
+            content: vec![ToolResponseContent::Text { text }],
+            is_error: None,
+            meta: None,
+        })
+    }
+
+    /// Route a tool call to a composite tool or the upstream server that provides it
+    async fn dispatch_tool(&self, req: CallToolRequest) -> Result<CallToolResponse> {
+        if let Some(composite) = self.config.composite_tools.get(&req.name) {
+            // Refuse up front rather than after earlier steps have run
+            let access = self.access();
+            if let Some(step) = composite
+                .steps
+                .iter()
+                .find(|step| !access.allows_tool(&step.server, &step.tool))
+            {
+                warn!(
+                    "Denied composite tool {} to {}: step {}{TOOL_SEPARATOR}{}",
+                    req.name, self.identity, step.server, step.tool
+                );
+                anyhow::bail!("Tool {} is not allowed", req.name);
+            }
+            return self.handle_composite(composite, req).await;
+        }
+
         // Check if server is specified in the request
         let server_name_parts = req.name.split(TOOL_SEPARATOR).collect::<Vec<&str>>();
 
@@ -435,58 +1823,470 @@ impl McpProxy {
             let server_name = server_name_parts[0];
             let function_name = server_name_parts[1];
             if let Some(server) = self.config.servers.get(server_name) {
-                // Extract env_vars from meta if they exist
+                let mut req = req.clone();
+                req.name = function_name.to_string();
+                return self.call_server_tool(server_name, server, req).await;
+            }
+            anyhow::bail!("Specified server {} not found", server_name);
+        }
 
-                let env_vars = Self::get_env_vars(&req);
+        // If no server specified, find the first server that has the tool
+        let server_name = {
+            let tools = self.tools_cache.lock().await;
+            tools
+                .iter()
+                .find(|(_, server_tools)| server_tools.iter().any(|s| req.name == s.name))
+                .map(|(server_name, _)| server_name.clone())
+        };
+        if let Some(server_name) = server_name {
+            if let Some(server) = self.config.servers.get(&server_name) {
+                return self.call_server_tool(&server_name, server, req).await;
+            }
+        }
 
-                if let Ok(client) = self
-                    .get_or_create_client(&server_name, server, env_vars)
-                    .await
+        anyhow::bail!("Tool {} not found in any server", req.name)
+    }
+
+    /// Forward a tool call to a specific upstream server
+    async fn call_server_tool(
+        &self,
+        server_name: &str,
+        server: &ProxyMcpServer,
+        req: CallToolRequest,
+    ) -> Result<CallToolResponse> {
+        if !self.access().allows_tool(server_name, &req.name) {
+            warn!(
+                "Denied tool {} on server {} to {}",
+                req.name, server_name, self.identity
+            );
+            anyhow::bail!("Tool {} on server {} is not allowed", req.name, server_name);
+        }
+        if let Err(limited) =
+            self.rate_limiter
+                .check(&self.rate_limits(server_name, server, &req.name))
+        {
+            warn!("{} for {}", limited, self.identity);
+            return Ok(limited.response());
+        }
+
+        let decision = {
+            let annotations = self.annotations_cache.lock().await;
+            self.tool_decision(server_name, &req.name, &annotations)
+        };
+        if let ToolDecision::Blocked(reason) = decision {
+            anyhow::bail!(
+                "Tool {} on server {} is not allowed: {}",
+                req.name,
+                server_name,
+                reason
+            );
+        }
+
+        if server
+            .tools
+            .get(&req.name)
+            .is_some_and(|tool| tool.require_approval)
+        {
+            let rejection = match self
+                .approvals
+                .request(server_name, &req.name, req.arguments.clone())
+                .await
+            {
+                ApprovalOutcome::Approved => None,
+                ApprovalOutcome::Rejected(reason) => Some(format!(
+                    "Call to {}{TOOL_SEPARATOR}{} was rejected by a reviewer{}",
+                    server_name,
+                    req.name,
+                    reason.map(|r| format!(": {}", r)).unwrap_or_default()
+                )),
+                ApprovalOutcome::TimedOut => Some(format!(
+                    "Call to {}{TOOL_SEPARATOR}{} was not approved within {} seconds",
+                    server_name, req.name, self.config.approval.timeout
+                )),
+            };
+            if let Some(text) = rejection {
+                return Ok(CallToolResponse {
+                    content: vec![ToolResponseContent::Text { text }],
+                    is_error: Some(true),
+                    meta: None,
+                });
+            }
+        }
+
+        // Extract env_vars from meta if they exist
+        let env_vars = policy::filter_env(
+            server_name,
+            Self::get_env_vars(&req),
+            server.env_policy.as_ref(),
+        )?;
+
+        let cache_key = match &self.result_cache {
+            Some(_) if self.is_cacheable(server_name, server, &req.name).await => {
+                Some(ResultCache::key(server_name, &req, env_vars.as_ref()))
+            }
+            _ => None,
+        };
+        if let (Some(cache), Some(key)) = (&self.result_cache, &cache_key) {
+            if !cache::is_bypassed(&req) {
+                if let Some(mut response) = cache.lock().await.get(key) {
+                    debug!("Cache hit for tool {} on server {}", req.name, server_name);
+                    set_meta(&mut response, "cache", json!("hit"));
+                    return Ok(self
+                        .limit_output(server_name, server, &req.name, response)
+                        .await);
+                }
+            }
+        }
+
+        let retry = self.retry_policy(server_name, server, &req.name).await;
+        let max_attempts = retry.map(|r| r.max_attempts.max(1)).unwrap_or(1);
+        let mut attempt = 0;
+        let mut response = loop {
+            attempt += 1;
+            let slot = match self.limiters.get(server_name) {
+                Some(limiter) => Some(limiter.acquire().await?),
+                None => None,
+            };
+            let breaker = self.breakers.get(server_name);
+            if let Some(Err(retry_after)) = breaker.map(|b| b.try_acquire()) {
+                return Err(ProxyError(format!(
+                    "Server {} is unavailable, circuit breaker open for another {}s",
+                    server_name,
+                    retry_after.as_secs_f64().ceil()
+                ))
+                .into());
+            }
+            let result = self
+                .request_tool(server_name, server, &req, env_vars.clone())
+                .await;
+            drop(slot);
+            if let Some(breaker) = breaker {
+                breaker.record(&result);
+            }
+            let error = match result {
+                Ok(response) => break response,
+                Err(e) => e,
+            };
+            match retry {
+                Some(retry)
+                    if attempt < max_attempts
+                        && retry::classify(&error)
+                            .is_some_and(|class| retry.retry_on.contains(&class)) =>
                 {
-                    let mut req = req.clone();
-                    req.name = function_name.to_string();
-
-                    info!("Executing tool {} on server {}", function_name, server_name);
-                    debug!("Tool request: {:?}", req);
-                    let response = client
-                        .request(
-                            "tools/call",
-                            Some(serde_json::to_value(&req)?),
-                            RequestOptions::default()
-                                .timeout(Duration::from_secs(self.config.timeout.call)),
-                        )
-                        .await?;
-                    return Ok(serde_json::from_value(response)?);
+                    let delay = retry::backoff(retry, attempt);
+                    warn!(
+                        "Attempt {} of tool {} on server {} failed, retrying in {:?}: {}",
+                        attempt, req.name, server_name, delay, error
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Some(_) => {
+                    let text = if attempt > 1 {
+                        format!("{} (after {} attempts)", error, attempt)
+                    } else {
+                        error.to_string()
+                    };
+                    let mut response = CallToolResponse {
+                        content: vec![ToolResponseContent::Text { text }],
+                        is_error: Some(true),
+                        meta: None,
+                    };
+                    set_meta(&mut response, "attempts", json!(attempt));
+                    return Ok(response);
                 }
+                None => return Err(error),
             }
-            anyhow::bail!("Specified server {} not found", server_name);
+        };
+
+        if let (Some(cache), Some(key)) = (&self.result_cache, cache_key) {
+            if response.is_error != Some(true) {
+                cache.lock().await.insert(key, response.clone());
+            }
+        }
+        if retry.is_some() {
+            set_meta(&mut response, "attempts", json!(attempt));
         }
 
-        // If no server specified, find the first server that has the tool
-        let tools = self.tools_cache.lock().await;
-        for (server_name, server_tools) in tools.iter() {
-            if server_tools.iter().any(|s| req.name == s.name) {
-                if let Some(server) = self.config.servers.get(server_name) {
-                    let env_vars = Self::get_env_vars(&req);
-                    if let Ok(client) = self
-                        .get_or_create_client(server_name, server, env_vars)
-                        .await
-                    {
-                        let response = client
-                            .request(
-                                "tools/call",
-                                Some(serde_json::to_value(&req)?),
-                                RequestOptions::default()
-                                    .timeout(Duration::from_secs(self.config.timeout.call)),
-                            )
-                            .await?;
-                        return Ok(serde_json::from_value(response)?);
-                    }
+        Ok(self
+            .limit_output(server_name, server, &req.name, response)
+            .await)
+    }
+
+    /// Send a single `tools/call` request to an upstream server.
+    ///
+    /// For groups the endpoints are tried in the order chosen by the balancer,
+    /// failing over to the next one on timeouts and transport errors.
+    async fn request_tool(
+        &self,
+        server_name: &str,
+        server: &ProxyMcpServer,
+        req: &CallToolRequest,
+        env_vars: Option<HashMap<String, String>>,
+    ) -> Result<CallToolResponse> {
+        let (ProxyMcpServerType::Group { endpoints, .. }, Some(balancer)) =
+            (&server.server_type, self.balancers.get(server_name))
+        else {
+            return self
+                .request_endpoint(server_name, &server.server_type, req, env_vars)
+                .await;
+        };
+
+        let mut last_error = None;
+        for index in balancer.candidates() {
+            let client_key = endpoint_key(server_name, index);
+            let _in_flight = balancer.start(index);
+            match self
+                .request_endpoint(&client_key, &endpoints[index], req, env_vars.clone())
+                .await
+            {
+                Ok(response) => {
+                    balancer.record_success(index);
+                    return Ok(response);
+                }
+                // The endpoint answered, so it is healthy and another one would fail the same way
+                Err(e) if retry::classify(&e) == Some(RetryableError::Upstream) => {
+                    balancer.record_success(index);
+                    return Err(e);
+                }
+                // The proxy failed, which says nothing about the endpoint
+                Err(e) if retry::classify(&e).is_none() => return Err(e),
+                Err(e) => {
+                    warn!("Endpoint {} failed: {}", client_key, e);
+                    balancer.record_failure(index);
+                    last_error = Some(e);
                 }
             }
         }
+        Err(last_error.unwrap_or_else(|| {
+            ProxyError(format!("Group {} has no endpoints", server_name)).into()
+        }))
+    }
 
-        anyhow::bail!("Tool {} not found in any server", req.name)
+    async fn request_endpoint(
+        &self,
+        client_key: &str,
+        server_type: &ProxyMcpServerType,
+        req: &CallToolRequest,
+        env_vars: Option<HashMap<String, String>>,
+    ) -> Result<CallToolResponse> {
+        let upstream = self
+            .checkout(client_key, server_type, env_vars.clone())
+            .await?;
+
+        // A caller's own instance does not stand for the server
+        if env_vars.is_none() {
+            self.list_started(server_name(client_key));
+        }
+
+        info!("Executing tool {} on server {}", req.name, client_key);
+        debug!("Tool request: {:?}", req);
+        let result = upstream
+            .request(
+                "tools/call",
+                Some(serde_json::to_value(req)?),
+                Duration::from_secs(self.config.timeout.call),
+            )
+            .await;
+        let response = match (result, &upstream) {
+            (Ok(response), _) => response,
+            // A failed send is how a dropped SSE or WebSocket connection shows up
+            (Err(e), Upstream::Shared(connection))
+                if !matches!(server_type, ProxyMcpServerType::Stdio { .. })
+                    && retry::classify(&e) == Some(RetryableError::Transport) =>
+            {
+                self.connection_lost(client_key, connection, server_type, env_vars, e.to_string())
+                    .await;
+                return Err(e);
+            }
+            (Err(e), _) => return Err(e),
+        };
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// Calls in flight and queued for every server with a concurrency limit
+    pub fn limiter_status(&self) -> HashMap<String, LimiterStatus> {
+        self.limiters
+            .iter()
+            .map(|(name, limiter)| (name.clone(), limiter.status()))
+            .collect()
+    }
+
+    /// State of every server's circuit breaker
+    pub fn breaker_status(&self) -> HashMap<String, BreakerStatus> {
+        self.breakers
+            .iter()
+            .map(|(name, breaker)| (name.clone(), breaker.status()))
+            .collect()
+    }
+
+    /// Endpoint health of every server group
+    pub fn group_status(&self) -> HashMap<String, Vec<EndpointStatus>> {
+        self.balancers
+            .iter()
+            .map(|(name, balancer)| (name.clone(), balancer.status()))
+            .collect()
+    }
+
+    /// The retry policy for a tool, falling back to the server and global policies.
+    ///
+    /// Unless the policy allows retrying any tool, it only applies to tools
+    /// annotated as idempotent or read-only.
+    async fn retry_policy<'a>(
+        &'a self,
+        server_name: &str,
+        server: &'a ProxyMcpServer,
+        tool_name: &str,
+    ) -> Option<&'a RetryConfig> {
+        let retry = server
+            .tools
+            .get(tool_name)
+            .and_then(|tool| tool.retry.as_ref())
+            .or(server.retry.as_ref())
+            .or(self.config.retry.as_ref())?;
+        if !retry.idempotent_only {
+            return Some(retry);
+        }
+        let hints = self.annotations(server_name, tool_name).await?;
+        (hints.idempotent_hint == Some(true) || hints.read_only_hint == Some(true)).then_some(retry)
+    }
+
+    async fn annotations(&self, server_name: &str, tool_name: &str) -> Option<ToolAnnotations> {
+        self.annotations_cache
+            .lock()
+            .await
+            .get(server_name)
+            .and_then(|tools| tools.get(tool_name))
+            .cloned()
+    }
+
+    /// Whether results of a tool may be served from the result cache.
+    ///
+    /// An explicit per-tool `cache` setting wins. Otherwise the tool's
+    /// `readOnlyHint`/`idempotentHint` annotations decide when the server
+    /// reported any, and the server's `cache` setting when it did not.
+    async fn is_cacheable(
+        &self,
+        server_name: &str,
+        server: &ProxyMcpServer,
+        tool_name: &str,
+    ) -> bool {
+        cache::is_cacheable(
+            server.tools.get(tool_name).and_then(|tool| tool.cache),
+            self.annotations(server_name, tool_name).await.as_ref(),
+            server.cache,
+        )
+    }
+
+    async fn limit_output(
+        &self,
+        server_name: &str,
+        server: &ProxyMcpServer,
+        tool_name: &str,
+        response: CallToolResponse,
+    ) -> CallToolResponse {
+        match self.output_limit(server, tool_name) {
+            Some(limit) => {
+                let owner = OutputOwner {
+                    subject: self.identity.subject.clone(),
+                    server: server_name.to_string(),
+                    tool: tool_name.to_string(),
+                };
+                let mut outputs = self.outputs.lock().await;
+                output::apply_limit(response, limit, &owner, &mut outputs)
+            }
+            None => response,
+        }
+    }
+
+    /// Tool calls waiting for approval
+    pub fn approvals(&self) -> &ApprovalManager {
+        &self.approvals
+    }
+
+    /// Hit and miss counts of the result cache, if it is enabled
+    pub async fn cache_stats(&self) -> Option<CacheStats> {
+        match &self.result_cache {
+            Some(cache) => Some(cache.lock().await.stats()),
+            None => None,
+        }
+    }
+
+    /// The response size limit for a tool, falling back to the server and global limits
+    fn output_limit<'a>(
+        &'a self,
+        server: &'a ProxyMcpServer,
+        tool_name: &str,
+    ) -> Option<&'a OutputLimitConfig> {
+        server
+            .tools
+            .get(tool_name)
+            .and_then(|tool| tool.output_limit.as_ref())
+            .or(server.output_limit.as_ref())
+            .or(self.config.output_limit.as_ref())
+    }
+
+    /// Read back a tool output stored by the proxy for this caller
+    async fn read_resource(&self, req: ReadResourceRequest) -> Result<ReadResourceResponse> {
+        self.outputs
+            .lock()
+            .await
+            .read(&req.uri, &self.identity, &self.access())
+    }
+
+    /// Run the steps of a composite tool in order and combine their content
+    async fn handle_composite(
+        &self,
+        composite: &CompositeToolConfig,
+        req: CallToolRequest,
+    ) -> Result<CallToolResponse> {
+        let input = serde_json::to_value(req.arguments.clone().unwrap_or_default())?;
+        let mut ctx = json!({ "input": input, "steps": {} });
+        let mut content = Vec::new();
+
+        for (index, step) in composite.steps.iter().enumerate() {
+            let step_id = step.id.clone().unwrap_or_else(|| index.to_string());
+            let server = self.config.servers.get(&step.server).ok_or_else(|| {
+                anyhow::anyhow!("Step {}: server {} not found", step_id, step.server)
+            })?;
+            let arguments = composite::render(&step.arguments, &ctx)
+                .and_then(composite::into_arguments)
+                .map_err(|e| anyhow::anyhow!("Step {}: {}", step_id, e))?;
+
+            let step_req = CallToolRequest {
+                name: step.tool.clone(),
+                arguments,
+                meta: req.meta.clone(),
+            };
+            let response = self
+                .call_server_tool(&step.server, server, step_req)
+                .await?;
+
+            if response.is_error == Some(true) {
+                let mut error_content = vec![ToolResponseContent::Text {
+                    text: format!(
+                        "Step {} ({}{TOOL_SEPARATOR}{}) failed",
+                        step_id, step.server, step.tool
+                    ),
+                }];
+                error_content.extend(response.content);
+                return Ok(CallToolResponse {
+                    content: error_content,
+                    is_error: Some(true),
+                    meta: response.meta,
+                });
+            }
+
+            ctx["steps"][&step_id] = composite::step_result(&response);
+            if step.include {
+                content.extend(response.content);
+            }
+        }
+
+        Ok(CallToolResponse {
+            content,
+            is_error: None,
+            meta: None,
+        })
     }
 
     /// Get the current state of the proxy's caches
@@ -496,10 +2296,368 @@ impl McpProxy {
     pub async fn state(&self) -> Result<McpCache> {
         let tools_cache = self.tools_cache.lock().await;
         let resources_cache = self.resources_cache.lock().await;
+        let annotations_cache = self.annotations_cache.lock().await;
 
         Ok(McpCache {
             tools: tools_cache.clone(),
             resources: resources_cache.clone(),
+            annotations: annotations_cache.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stdio MCP server in `sh`, answering `tools/call` for `slow` after a
+    /// second. `region` answers with `$REGION` and `exit` stops the server.
+    /// It creates the file `$MARKER` when it starts.
+    const FAKE_SERVER: &str = r#"
+[ -n "$MARKER" ] && touch "$MARKER"
+while IFS= read -r line; do
+  id=$(printf '%s\n' "$line" | sed -n 's/^{"id":\([0-9]*\),.*/\1/p')
+  [ -z "$id" ] && continue
+  case "$line" in
+    *'"method":"tools/list"'*)
+      result='{"tools":[{"name":"slow","inputSchema":{"type":"object"}},{"name":"fast","inputSchema":{"type":"object"}}]}' ;;
+    *'"method":"tools/call"'*'"name":"region"'*)
+      result="{\"content\":[{\"type\":\"text\",\"text\":\"$REGION\"}]}" ;;
+    *'"method":"tools/call"'*'"name":"exit"'*)
+      exit 1 ;;
+    *'"method":"tools/call"'*'"name":"slow"'*)
+      sleep 1
+      result='{"content":[{"type":"text","text":"done"}]}' ;;
+    *'"method":"tools/call"'*)
+      result='{"content":[{"type":"text","text":"done"}]}' ;;
+    *) result='{}' ;;
+  esac
+  printf '{"jsonrpc":"2.0","id":%s,"result":%s}\n' "$id" "$result"
+done
+"#;
+
+    /// A `fake` server running `FAKE_SERVER` with the given server and global settings
+    fn config(settings: Value, global: Value) -> Arc<Config> {
+        let mut server = json!({
+            "type": "stdio",
+            "command": "sh",
+            "args": ["-c", FAKE_SERVER],
+        });
+        server
+            .as_object_mut()
+            .unwrap()
+            .extend(settings.as_object().unwrap().clone());
+        let mut config = json!({ "servers": { "fake": server } });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(global.as_object().unwrap().clone());
+        Arc::new(serde_json::from_value(config).unwrap())
+    }
+
+    /// A directory removed with everything in it when dropped
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("mcp-proxy-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.0.join(file).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    async fn eventually(mut condition: impl FnMut() -> bool, what: &str) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out waiting for {}", what);
+    }
+
+    fn region(region: &str) -> Option<Value> {
+        Some(json!({ "REGION": region }))
+    }
+
+    /// The `REGION` of each running per-caller instance
+    async fn instances(proxy: &McpProxy) -> Vec<String> {
+        let mut regions: Vec<String> = proxy
+            .clients
+            .lock()
+            .await
+            .values()
+            .filter_map(|connection| connection.env_vars.as_ref()?.get("REGION").cloned())
+            .collect();
+        regions.sort();
+        regions
+    }
+
+    async fn instance_proxy(instances: Value) -> McpProxy {
+        let settings = json!({ "env_policy": { "allow": { "REGION": "[a-z]+" } } });
+        McpProxy::initialize(config(settings, json!({ "instances": instances })))
+            .await
+            .unwrap()
+    }
+
+    async fn tool_names(proxy: &McpProxy) -> Vec<String> {
+        let mut names: Vec<String> = proxy
+            .list_tools()
+            .await
+            .into_iter()
+            .map(|tool| tool.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn call(tool: &str, env_vars: Option<Value>) -> CallToolRequest {
+        CallToolRequest {
+            name: format!("fake{TOOL_SEPARATOR}{tool}"),
+            arguments: None,
+            meta: env_vars.map(|env_vars| json!({ "env_vars": env_vars })),
+        }
+    }
+
+    fn text(response: &CallToolResponse) -> &str {
+        match &response.content[..] {
+            [ToolResponseContent::Text { text }] => text,
+            content => panic!("unexpected content {:?}", content),
+        }
+    }
+
+    /// Wait until a call is in flight on one of the proxy's connections
+    async fn busy(proxy: &McpProxy) {
+        for _ in 0..500 {
+            let clients = proxy.clients.lock().await;
+            if clients
+                .values()
+                .any(|connection| connection.in_flight.load(Ordering::SeqCst) > 0)
+            {
+                return;
+            }
+            drop(clients);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no call reached the server");
+    }
+
+    /// Retry every error class, and open the breaker on the first failure
+    fn resilience() -> Value {
+        json!({
+            "retry": {
+                "max_attempts": 3,
+                "initial_backoff_ms": 1,
+                "retry_on": ["timeout", "transport", "upstream"],
+                "idempotent_only": false,
+            },
+            "circuit_breaker": { "failure_threshold": 1, "open_secs": 60 },
         })
     }
+
+    fn assert_breaker_closed(proxy: &McpProxy) {
+        let breakers = proxy.breaker_status();
+        assert_eq!(breakers["fake"].state, "closed");
+        assert_eq!(breakers["fake"].consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn a_saturated_limiter_is_not_retried_or_counted() {
+        let mut settings = resilience();
+        settings["concurrency"] = json!({ "max_in_flight": 1, "max_queue": 0 });
+        let proxy = McpProxy::initialize(config(settings, json!({})))
+            .await
+            .unwrap();
+
+        let slow = tokio::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.handle_tool(call("slow", None)).await }
+        });
+        busy(&proxy).await;
+        let error = proxy.handle_tool(call("fast", None)).await.unwrap_err();
+        assert!(error.is::<ProxyError>(), "{}", error);
+        assert_breaker_closed(&proxy);
+
+        assert_eq!(text(&slow.await.unwrap().unwrap()), "done");
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn busy_instances_are_not_retried_or_counted() {
+        let mut settings = resilience();
+        settings["env_policy"] = json!({ "allow": { "REGION": "[a-z]+" } });
+        let proxy = McpProxy::initialize(config(
+            settings,
+            json!({ "instances": { "max_instances": 1 } }),
+        ))
+        .await
+        .unwrap();
+
+        let slow = tokio::spawn({
+            let proxy = proxy.clone();
+            async move {
+                proxy
+                    .handle_tool(call("slow", Some(json!({ "REGION": "eu" }))))
+                    .await
+            }
+        });
+        busy(&proxy).await;
+        let response = proxy
+            .handle_tool(call("fast", Some(json!({ "REGION": "us" }))))
+            .await
+            .unwrap();
+        assert_eq!(response.is_error, Some(true));
+        assert!(text(&response).contains("busy"), "{}", text(&response));
+        assert_eq!(response.meta.unwrap()["attempts"], json!(1));
+        assert_breaker_closed(&proxy);
+
+        assert_eq!(text(&slow.await.unwrap().unwrap()), "done");
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn lazy_servers_start_on_their_first_call() {
+        let dir = TempDir::new();
+        let marker = dir.path("started");
+        let settings = json!({
+            "start": "lazy",
+            "MARKER": marker,
+            "tools": { "slow": { "input_schema": { "type": "object" } } },
+        });
+        let global = json!({ "catalog_file": dir.path("catalog.json") });
+        let proxy = McpProxy::initialize(config(settings, global))
+            .await
+            .unwrap();
+
+        assert!(!std::path::Path::new(&marker).exists());
+        assert!(proxy.clients.lock().await.is_empty());
+        assert_eq!(tool_names(&proxy).await, ["fake---slow"]);
+
+        let response = proxy.handle_tool(call("fast", None)).await.unwrap();
+        assert_eq!(text(&response), "done");
+        assert!(std::path::Path::new(&marker).exists());
+        // The server lists its own catalog once it runs, and it is saved
+        let catalog = dir.path("catalog.json");
+        eventually(
+            || std::fs::read_to_string(&catalog).is_ok_and(|saved| saved.contains("fast")),
+            "the fetched catalog",
+        )
+        .await;
+        assert_eq!(tool_names(&proxy).await, ["fake---fast", "fake---slow"]);
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn lazy_servers_are_listed_from_the_saved_catalog() {
+        let dir = TempDir::new();
+        let marker = dir.path("started");
+        let catalog = json!({
+            "tools": {
+                "fake": [{ "name": "saved", "inputSchema": { "type": "object" } }],
+            },
+            "resources": {},
+        });
+        std::fs::write(dir.path("catalog.json"), catalog.to_string()).unwrap();
+        let settings = json!({
+            "start": "lazy",
+            "MARKER": marker,
+            "tools": { "slow": { "input_schema": { "type": "object" } } },
+        });
+        let global = json!({ "catalog_file": dir.path("catalog.json") });
+        let proxy = McpProxy::initialize(config(settings, global))
+            .await
+            .unwrap();
+
+        assert_eq!(tool_names(&proxy).await, ["fake---saved"]);
+        assert!(!std::path::Path::new(&marker).exists());
+        assert!(proxy.clients.lock().await.is_empty());
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn each_environment_gets_its_own_instance() {
+        let proxy = instance_proxy(json!({})).await;
+
+        for caller in ["eu", "us", "eu"] {
+            let response = proxy
+                .handle_tool(call("region", region(caller)))
+                .await
+                .unwrap();
+            assert_eq!(text(&response), caller);
+        }
+        assert_eq!(instances(&proxy).await, ["eu", "us"]);
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn the_least_recently_used_idle_instance_is_evicted() {
+        let proxy = instance_proxy(json!({ "max_instances": 2 })).await;
+
+        for caller in ["eu", "us", "eu", "ap"] {
+            proxy
+                .handle_tool(call("region", region(caller)))
+                .await
+                .unwrap();
+        }
+        assert_eq!(instances(&proxy).await, ["ap", "eu"]);
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn idle_instances_are_stopped() {
+        let proxy = instance_proxy(json!({ "idle_secs": 1 })).await;
+
+        proxy
+            .handle_tool(call("region", region("eu")))
+            .await
+            .unwrap();
+        assert_eq!(instances(&proxy).await, ["eu"]);
+        for _ in 0..300 {
+            if instances(&proxy).await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(instances(&proxy).await.is_empty());
+        proxy.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn stopped_instances_restart_with_their_own_environment() {
+        let proxy = instance_proxy(json!({})).await;
+
+        proxy
+            .handle_tool(call("region", region("us")))
+            .await
+            .unwrap();
+        let response = proxy.handle_tool(call("exit", region("eu"))).await;
+        assert!(response.is_err());
+        for _ in 0..300 {
+            if instances(&proxy).await == ["us"] {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Nothing restarts the instance until its caller is back
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(instances(&proxy).await, ["us"]);
+
+        let response = proxy
+            .handle_tool(call("region", region("eu")))
+            .await
+            .unwrap();
+        assert_eq!(text(&response), "eu");
+        assert_eq!(instances(&proxy).await, ["eu", "us"]);
+        proxy.shutdown().await;
+    }
 }
@@ -0,0 +1,202 @@
+//! Stdio transport to a child process that reports how the child exited.
+//!
+//! Works like `async_mcp`'s `ClientStdioTransport`, which keeps its child
+//! private, but records the exit status once the child closes its stdout so
+//! supervision can report it.
+use anyhow::Result;
+use async_mcp::transport::{Message, Transport};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+/// How long to wait for a child to exit after it closed its stdout
+const EXIT_WAIT: Duration = Duration::from_secs(1);
+const GRACEFUL_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct StdioTransport {
+    stdin: Arc<Mutex<Option<BufWriter<ChildStdin>>>>,
+    stdout: Arc<Mutex<Option<BufReader<ChildStdout>>>>,
+    child: Arc<Mutex<Option<Child>>>,
+    exit_status: Arc<std::sync::Mutex<Option<ExitStatus>>>,
+    program: String,
+    args: Vec<String>,
+    env: Option<HashMap<String, String>>,
+}
+
+impl StdioTransport {
+    pub fn new(program: &str, args: &[String], env: Option<HashMap<String, String>>) -> Self {
+        Self {
+            stdin: Arc::new(Mutex::new(None)),
+            stdout: Arc::new(Mutex::new(None)),
+            child: Arc::new(Mutex::new(None)),
+            exit_status: Arc::new(std::sync::Mutex::new(None)),
+            program: program.to_string(),
+            args: args.to_vec(),
+            env,
+        }
+    }
+
+    /// Exit status of the child, known once it has exited
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status.lock().ok().and_then(|status| *status)
+    }
+
+    async fn reap(&self) {
+        let mut child = self.child.lock().await;
+        let Some(process) = child.as_mut() else {
+            return;
+        };
+        if let Ok(Ok(status)) = tokio::time::timeout(EXIT_WAIT, process.wait()).await {
+            debug!("Process {} exited with {}", self.program, status);
+            if let Ok(mut exit_status) = self.exit_status.lock() {
+                *exit_status = Some(status);
+            }
+            *child = None;
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for StdioTransport {
+    async fn receive(&self) -> Result<Option<Message>> {
+        let mut line = String::new();
+        let bytes_read = {
+            let mut stdout = self.stdout.lock().await;
+            let stdout = stdout
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
+            stdout.read_line(&mut line).await?
+        };
+        if bytes_read == 0 {
+            debug!("Process {} closed its stdout", self.program);
+            self.reap().await;
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        let mut stdin = self.stdin.lock().await;
+        let stdin = stdin
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Transport not opened"))?;
+        let serialized = serde_json::to_string(message)?;
+        stdin.write_all(serialized.as_bytes()).await?;
+        stdin.write_all(b"\n").await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<()> {
+        let mut command = tokio::process::Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+        for (key, value) in self.env.iter().flatten() {
+            command.env(key, value);
+        }
+
+        let mut child = command.spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Child process stdin not available"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Child process stdout not available"))?;
+
+        *self.stdin.lock().await = Some(BufWriter::new(stdin));
+        *self.stdout.lock().await = Some(BufReader::new(stdout));
+        *self.child.lock().await = Some(child);
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        // Closing stdin asks a well-behaved server to exit
+        *self.stdin.lock().await = None;
+
+        let mut child = self.child.lock().await;
+        let Some(process) = child.as_mut() else {
+            return Ok(());
+        };
+        let status = match tokio::time::timeout(GRACEFUL_TIMEOUT, process.wait()).await {
+            Ok(status) => status?,
+            Err(_) => {
+                debug!("Process {} did not exit, killing it", self.program);
+                process.kill().await?;
+                process.wait().await?
+            }
+        };
+        if let Ok(mut exit_status) = self.exit_status.lock() {
+            *exit_status = Some(status);
+        }
+        *child = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> StdioTransport {
+        StdioTransport::new("sh", &["-c".to_string(), script.to_string()], None)
+    }
+
+    /// Whether a process is still running, a zombie waiting to be reaped is not
+    fn is_running(pid: u32) -> bool {
+        std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .is_ok_and(|stat| !stat.rsplit(')').next().unwrap_or("").starts_with(" Z"))
+    }
+
+    #[tokio::test]
+    async fn reports_the_exit_status() {
+        let transport = sh("exit 3");
+        transport.open().await.unwrap();
+        assert!(transport.exit_status().is_none());
+
+        assert!(transport.receive().await.unwrap().is_none());
+        assert_eq!(transport.exit_status().unwrap().code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn passes_the_environment() {
+        let env = HashMap::from([("REGION".to_string(), "eu".to_string())]);
+        let script = r#"printf '{"jsonrpc":"2.0","method":"%s"}\n' "$REGION""#;
+        let transport =
+            StdioTransport::new("sh", &["-c".to_string(), script.to_string()], Some(env));
+        transport.open().await.unwrap();
+
+        match transport.receive().await.unwrap() {
+            Some(Message::Notification(notification)) => assert_eq!(notification.method, "eu"),
+            _ => panic!("expected a notification"),
+        }
+    }
+
+    #[tokio::test]
+    async fn dropping_the_transport_kills_the_child() {
+        let transport = sh("exec sleep 60");
+        transport.open().await.unwrap();
+        let pid = transport.child.lock().await.as_ref().unwrap().id().unwrap();
+        assert!(is_running(pid));
+
+        drop(transport);
+        for _ in 0..100 {
+            if !is_running(pid) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("process {} still runs", pid);
+    }
+}
@@ -0,0 +1,213 @@
+//! Restart bookkeeping for upstream servers that stop.
+//!
+//! Each exit or lost connection is recorded with its reason. A restart is
+//! allowed while fewer than `max_restarts` happened within `window_secs`, after
+//! an exponential backoff.
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::retry::ProxyError;
+use crate::types::RestartConfig;
+
+#[derive(Default)]
+struct RestartState {
+    restarts: u32,
+    recent: VecDeque<Instant>,
+    last_exit: Option<String>,
+    last_exit_at: Option<u64>,
+    gave_up: bool,
+}
+
+/// Restart history of a server for status output
+#[derive(Debug, Clone, Serialize)]
+pub struct RestartStatus {
+    pub restarts: u32,
+    pub last_exit: Option<String>,
+    /// Unix timestamp in seconds
+    pub last_exit_at: Option<u64>,
+    /// The restart budget is used up and the server is not restarted
+    pub gave_up: bool,
+}
+
+#[derive(Default)]
+pub struct Supervisor {
+    state: Mutex<HashMap<String, RestartState>>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn prune(state: &mut RestartState, config: &RestartConfig, now: Instant) {
+    let window = Duration::from_secs(config.window_secs);
+    while state
+        .recent
+        .front()
+        .is_some_and(|at| now.duration_since(*at) >= window)
+    {
+        state.recent.pop_front();
+    }
+}
+
+impl Supervisor {
+    /// Record that `key` exited, returning the delay before restarting it or
+    /// `None` when the restart budget is used up.
+    pub fn record_exit(&self, key: &str, reason: &str, config: &RestartConfig) -> Option<Duration> {
+        let mut states = self.state.lock().ok()?;
+        let state = states.entry(key.to_string()).or_default();
+        let now = Instant::now();
+        prune(state, config, now);
+        state.last_exit = Some(reason.to_string());
+        state.last_exit_at = Some(unix_now());
+
+        let recent = state.recent.len() as u32;
+        if recent >= config.max_restarts {
+            state.gave_up = true;
+            return None;
+        }
+        state.recent.push_back(now);
+        state.restarts += 1;
+
+        let delay = config
+            .initial_backoff_ms
+            .saturating_mul(1u64 << recent.min(16))
+            .min(config.max_backoff_ms);
+        Some(Duration::from_millis(delay))
+    }
+
+    /// Fails while the restart budget of `key` is used up
+    pub fn check(&self, key: &str, config: &RestartConfig) -> anyhow::Result<()> {
+        let Ok(mut states) = self.state.lock() else {
+            return Ok(());
+        };
+        let Some(state) = states.get_mut(key) else {
+            return Ok(());
+        };
+        if !state.gave_up {
+            return Ok(());
+        }
+        prune(state, config, Instant::now());
+        if (state.recent.len() as u32) < config.max_restarts {
+            state.gave_up = false;
+            return Ok(());
+        }
+        Err(ProxyError(format!(
+            "Server {} stopped {} times in {}s and is not restarted, last exit: {}",
+            key,
+            state.recent.len(),
+            config.window_secs,
+            state.last_exit.as_deref().unwrap_or("unknown")
+        ))
+        .into())
+    }
+
+    pub fn status(&self) -> HashMap<String, RestartStatus> {
+        let Ok(states) = self.state.lock() else {
+            return HashMap::new();
+        };
+        states
+            .iter()
+            .map(|(key, state)| {
+                (
+                    key.clone(),
+                    RestartStatus {
+                        restarts: state.restarts,
+                        last_exit: state.last_exit.clone(),
+                        last_exit_at: state.last_exit_at,
+                        gave_up: state.gave_up,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(window_secs: u64) -> RestartConfig {
+        RestartConfig {
+            max_restarts: 3,
+            window_secs,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 300,
+        }
+    }
+
+    #[test]
+    fn restarts_back_off_exponentially_up_to_the_cap() {
+        let supervisor = Supervisor::default();
+        let config = config(60);
+        let delays: Vec<_> = (0..3)
+            .map(|_| supervisor.record_exit("files", "exit status: 1", &config))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(300)),
+            ]
+        );
+        assert!(supervisor.check("files", &config).is_ok());
+    }
+
+    #[test]
+    fn gives_up_once_the_budget_is_used() {
+        let supervisor = Supervisor::default();
+        let config = config(60);
+        for _ in 0..3 {
+            assert!(supervisor
+                .record_exit("files", "crashed", &config)
+                .is_some());
+        }
+        assert_eq!(supervisor.record_exit("files", "crashed", &config), None);
+
+        let error = supervisor.check("files", &config).unwrap_err();
+        assert!(error.is::<ProxyError>());
+        assert!(
+            error.to_string().contains("last exit: crashed"),
+            "{}",
+            error
+        );
+
+        let status = &supervisor.status()["files"];
+        assert_eq!(status.restarts, 3);
+        assert!(status.gave_up);
+        assert_eq!(status.last_exit.as_deref(), Some("crashed"));
+    }
+
+    #[test]
+    fn budget_is_tracked_per_server() {
+        let supervisor = Supervisor::default();
+        let config = config(60);
+        for _ in 0..4 {
+            supervisor.record_exit("files", "crashed", &config);
+        }
+        assert!(supervisor.check("files", &config).is_err());
+        assert!(supervisor.check("search", &config).is_ok());
+        assert_eq!(
+            supervisor.record_exit("search", "crashed", &config),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn restarts_outside_the_window_are_forgotten() {
+        let supervisor = Supervisor::default();
+        let config = config(0);
+        for _ in 0..5 {
+            assert_eq!(
+                supervisor.record_exit("files", "crashed", &config),
+                Some(Duration::from_millis(100))
+            );
+        }
+        assert!(supervisor.check("files", &config).is_ok());
+    }
+}
@@ -1,14 +1,476 @@
+use regex::Regex;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ProxyServerConfig {
     pub servers: HashMap<String, ProxyMcpServer>,
-    pub port: u16,
+    /// Port to accept clients on on all interfaces, used when `listen` is empty
+    pub port: Option<u16>,
+    /// Addresses to accept clients on
+    #[serde(default)]
+    pub listen: Vec<ListenAddress>,
     #[serde(default)]
     pub timeout: TimeoutConfig,
+    #[serde(default)]
+    pub composite_tools: HashMap<String, CompositeToolConfig>,
+    /// Expose only the `search_tools`, `describe_tool` and `call_tool` meta-tools
+    #[serde(default)]
+    pub tool_search: bool,
+    /// Default response size limit for all tools
+    pub output_limit: Option<OutputLimitConfig>,
+    /// Enables caching results of read-only and idempotent tool calls
+    pub cache: Option<CacheConfig>,
+    /// Only expose tools annotated with `readOnlyHint: true`
+    #[serde(default)]
+    pub read_only: bool,
+    /// Tool name globs treated as mutating in read-only mode, e.g. `write_*`
+    #[serde(default)]
+    pub mutating_patterns: Vec<String>,
+    #[serde(default)]
+    pub approval: ApprovalConfig,
+    /// Admin HTTP endpoint, disabled when not set
+    pub admin: Option<AdminConfig>,
+    /// Default retry policy for failed upstream calls
+    pub retry: Option<RetryConfig>,
+    /// Default circuit breaker for every upstream server
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// How servers that stop or lose their connection are restarted
+    #[serde(default)]
+    pub restart: RestartConfig,
+    /// Limits on instances started for callers that supply `_meta.env_vars`
+    #[serde(default)]
+    pub instances: InstanceConfig,
+    /// Limits on tool calls across all servers and per client
+    #[serde(default)]
+    pub rate_limits: RateLimitsConfig,
+    /// Credentials clients must present, any client is accepted when not set
+    pub auth: Option<ProxyTransportAuth>,
+    /// What each authenticated client may use, everything when not set
+    pub authorization: Option<AuthorizationConfig>,
+    /// Transports clients connect with, SSE and WebSocket when not set
+    #[serde(default = "default_transports")]
+    pub transports: Vec<DownstreamTransport>,
+    /// File the fetched catalogs are saved to, lazy servers are listed from it
+    pub catalog_file: Option<String>,
+}
+
+/// An address the proxy accepts clients on
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum ListenAddress {
+    /// `host:port`, e.g. `127.0.0.1:3004` or `[::1]:3004`
+    Tcp(String),
+    /// A Unix domain socket
+    Unix {
+        unix: String,
+        /// Octal permissions of the socket file, e.g. `"660"`
+        mode: Option<String>,
+    },
+}
+
+impl std::fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "http://{}", address),
+            ListenAddress::Unix { unix, .. } => write!(f, "unix:{}", unix),
+        }
+    }
+}
+
+/// A transport the proxy serves clients on
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(tag = "type")]
+pub enum DownstreamTransport {
+    /// Events are streamed from `path`, messages are POSTed to `message_path`
+    #[serde(rename = "sse")]
+    Sse {
+        #[serde(default = "default_sse_path")]
+        path: String,
+        #[serde(default = "default_message_path")]
+        message_path: String,
+    },
+    #[serde(rename = "ws")]
+    Ws {
+        #[serde(default = "default_ws_path")]
+        path: String,
+    },
+    /// Streamable HTTP with sessions and resumable streams
+    #[serde(rename = "http")]
+    Http {
+        #[serde(default = "default_http_path")]
+        path: String,
+        /// Seconds without requests after which a session is dropped
+        #[serde(default = "default_session_timeout_secs")]
+        session_timeout_secs: u64,
+        /// Browser origins allowed to send requests, `*` for any. Entries
+        /// without a port allow every port of their host.
+        #[serde(default = "default_allowed_origins")]
+        allowed_origins: Vec<String>,
+    },
+}
+
+fn default_transports() -> Vec<DownstreamTransport> {
+    vec![
+        DownstreamTransport::Sse {
+            path: default_sse_path(),
+            message_path: default_message_path(),
+        },
+        DownstreamTransport::Ws {
+            path: default_ws_path(),
+        },
+    ]
+}
+
+fn default_sse_path() -> String {
+    "/sse".to_string()
+}
+
+fn default_message_path() -> String {
+    "/message".to_string()
+}
+
+fn default_ws_path() -> String {
+    "/ws".to_string()
+}
+
+fn default_http_path() -> String {
+    "/mcp".to_string()
+}
+
+pub(crate) fn default_allowed_origins() -> Vec<String> {
+    ["localhost", "127.0.0.1", "[::1]"]
+        .iter()
+        .flat_map(|host| [format!("http://{}", host), format!("https://{}", host)])
+        .collect()
+}
+
+fn default_session_timeout_secs() -> u64 {
+    1800
+}
+
+/// Upstream instances started per caller environment
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct InstanceConfig {
+    /// Instances per server, the least recently used idle one is stopped beyond this
+    #[serde(default = "default_max_instances")]
+    pub max_instances: usize,
+    /// Seconds without calls after which an instance is stopped
+    #[serde(default = "default_instance_idle_secs")]
+    pub idle_secs: u64,
+}
+
+impl Default for InstanceConfig {
+    fn default() -> Self {
+        Self {
+            max_instances: default_max_instances(),
+            idle_secs: default_instance_idle_secs(),
+        }
+    }
+}
+
+fn default_max_instances() -> usize {
+    16
+}
+fn default_instance_idle_secs() -> u64 {
+    600
+}
+
+/// Restarts of upstream servers whose process or connection ended
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct RestartConfig {
+    /// Restarts allowed within `window_secs` before the server is given up on
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    #[serde(default = "default_restart_window_secs")]
+    pub window_secs: u64,
+    #[serde(default = "default_restart_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_restart_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: default_max_restarts(),
+            window_secs: default_restart_window_secs(),
+            initial_backoff_ms: default_restart_initial_backoff_ms(),
+            max_backoff_ms: default_restart_max_backoff_ms(),
+        }
+    }
+}
+
+/// Which caller-supplied environment variables a server accepts
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct EnvPolicy {
+    /// Allowed keys mapped to a regex the whole value must match
+    #[serde(default)]
+    pub allow: HashMap<String, ValuePattern>,
+    #[serde(default)]
+    pub on_violation: EnvViolation,
+}
+
+/// A regex the whole value must match, compiled when the config is loaded
+#[derive(Debug, Clone)]
+pub struct ValuePattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl ValuePattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex: Regex::new(&format!("^(?:{})$", pattern))?,
+        })
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+
+impl Serialize for ValuePattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl<'de> Deserialize<'de> for ValuePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::new(&pattern).map_err(|e| {
+            serde::de::Error::custom(format!("invalid env_policy pattern {}: {}", pattern, e))
+        })
+    }
+}
+
+impl JsonSchema for ValuePattern {
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// What happens to a call supplying variables outside the policy
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvViolation {
+    /// Fail the call
+    #[default]
+    Reject,
+    /// Drop the offending variables and run the call with the rest
+    Strip,
+}
+
+/// When a server is started
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StartMode {
+    /// At proxy startup
+    #[default]
+    Eager,
+    /// On the first call, listed from `catalog_file` or the tools with an
+    /// `input_schema` until then
+    Lazy,
+}
+
+/// Periodic pings to a server
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct KeepaliveConfig {
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub interval_secs: u64,
+    /// Seconds to wait for a pong before the connection is considered lost
+    #[serde(default = "default_keepalive_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_keepalive_interval_secs() -> u64 {
+    30
+}
+fn default_keepalive_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+fn default_restart_window_secs() -> u64 {
+    300
+}
+fn default_restart_initial_backoff_ms() -> u64 {
+    500
+}
+fn default_restart_max_backoff_ms() -> u64 {
+    30_000
+}
+
+/// Fail calls fast while an upstream server keeps failing
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive timeouts or transport failures that open the breaker
+    #[serde(default = "default_breaker_threshold")]
+    pub failure_threshold: u32,
+    /// Seconds the breaker stays open before a probe call is let through
+    #[serde(default = "default_breaker_open_secs")]
+    pub open_secs: u64,
+}
+
+/// How many calls a server handles at once and how many may wait for a slot
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ConcurrencyConfig {
+    pub max_in_flight: usize,
+    /// Calls allowed to wait for a slot before new ones are rejected
+    #[serde(default = "default_max_queue")]
+    pub max_queue: usize,
+    /// Milliseconds a queued call waits for a slot
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+/// A token bucket allowing `requests` calls per period, in bursts of up to
+/// `requests`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct RateLimitsConfig {
+    /// Shared by all calls through the proxy
+    pub global: Option<RateLimit>,
+    /// Applied to each authenticated client, API keys may set their own
+    pub per_identity: Option<RateLimit>,
+}
+
+/// Number of worker processes of a pooled stdio server
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PoolConfig {
+    /// Workers kept running even when idle
+    #[serde(default = "default_pool_min")]
+    pub min: usize,
+    pub max: usize,
+    /// Seconds a worker above `min` may stay idle before it is stopped
+    #[serde(default = "default_pool_idle_secs")]
+    pub idle_secs: u64,
+}
+
+fn default_pool_min() -> usize {
+    1
+}
+fn default_pool_idle_secs() -> u64 {
+    300
+}
+
+fn default_max_queue() -> usize {
+    16
+}
+fn default_queue_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_breaker_threshold() -> u32 {
+    5
+}
+fn default_breaker_open_secs() -> u64 {
+    30
+}
+
+/// Retries with exponential backoff for failed upstream calls
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct RetryConfig {
+    /// Total attempts including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    #[serde(default = "default_backoff_multiplier")]
+    pub multiplier: f64,
+    /// Randomize each delay between half and all of its computed value
+    #[serde(default = "default_true")]
+    pub jitter: bool,
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<RetryableError>,
+    /// Only retry tools annotated as idempotent or read-only
+    #[serde(default = "default_true")]
+    pub idempotent_only: bool,
+}
+
+/// Classes of upstream failures that can be retried
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryableError {
+    /// The call did not complete within `timeout.call`
+    Timeout,
+    /// The connection or child process failed
+    Transport,
+    /// The server answered with a JSON-RPC error
+    Upstream,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+fn default_initial_backoff_ms() -> u64 {
+    200
+}
+fn default_max_backoff_ms() -> u64 {
+    5000
+}
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+fn default_retry_on() -> Vec<RetryableError> {
+    vec![RetryableError::Timeout, RetryableError::Transport]
+}
+
+/// How calls to tools with `require_approval` are handled
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ApprovalConfig {
+    /// Seconds to wait for a decision before rejecting the call
+    #[serde(default = "default_approval_timeout")]
+    pub timeout: u64,
+    /// URL that receives a POST for every pending, decided or expired approval
+    pub webhook: Option<String>,
+    pub webhook_headers: Option<HashMap<String, String>>,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_approval_timeout(),
+            webhook: None,
+            webhook_headers: None,
+        }
+    }
+}
+
+fn default_approval_timeout() -> u64 {
+    300
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AdminConfig {
+    #[serde(default = "default_admin_host")]
+    pub host: String,
+    pub port: u16,
+    /// Bearer token required on every admin request
+    pub token: Option<String>,
+}
+
+fn default_admin_host() -> String {
+    "127.0.0.1".to_string()
 }
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct TimeoutConfig {
@@ -16,6 +478,9 @@ pub struct TimeoutConfig {
     pub list: u64,
     #[serde(default = "default_call_timeout")]
     pub call: u64,
+    /// Seconds to connect to a server and complete the initialize handshake
+    #[serde(default = "default_connect_timeout")]
+    pub connect: u64,
 }
 
 impl Default for TimeoutConfig {
@@ -23,6 +488,7 @@ impl Default for TimeoutConfig {
         Self {
             list: default_list_timeout(),
             call: default_call_timeout(),
+            connect: default_connect_timeout(),
         }
     }
 }
@@ -33,10 +499,147 @@ fn default_list_timeout() -> u64 {
 fn default_call_timeout() -> u64 {
     60
 }
+fn default_connect_timeout() -> u64 {
+    30
+}
+
+/// A virtual tool that runs a sequence of upstream tool calls
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct CompositeToolConfig {
+    pub description: Option<String>,
+    #[serde(default = "default_input_schema")]
+    pub input_schema: Value,
+    pub steps: Vec<CompositeStep>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct CompositeStep {
+    /// Name used to reference this step's result, defaults to the step index
+    pub id: Option<String>,
+    pub server: String,
+    pub tool: String,
+    /// Arguments for the upstream call, may contain `{{ ... }}` placeholders
+    #[serde(default)]
+    pub arguments: Value,
+    /// Whether this step's content is part of the combined response
+    #[serde(default = "default_true")]
+    pub include: bool,
+}
+
+fn default_input_schema() -> Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+fn default_true() -> bool {
+    true
+}
+
+/// Maximum size of a tool response and what to do with larger ones
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct OutputLimitConfig {
+    pub max_bytes: usize,
+    #[serde(default)]
+    pub overflow: OverflowAction,
+    /// Size of each chunk when reading a stored output back
+    #[serde(default = "default_chunk_bytes")]
+    pub chunk_bytes: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowAction {
+    /// Cut the response at the limit and append a marker
+    #[default]
+    Truncate,
+    /// Store the response and return a resource link to read it in chunks
+    Resource,
+}
+
+fn default_chunk_bytes() -> usize {
+    64 * 1024
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct CacheConfig {
+    /// Seconds a cached result stays valid
+    #[serde(default = "default_cache_ttl")]
+    pub ttl: u64,
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_cache_ttl() -> u64 {
+    300
+}
+fn default_cache_max_entries() -> usize {
+    1000
+}
+
+/// Behaviour hints an upstream server reports in a tool's `annotations`
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destructive_hint: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotent_hint: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_world_hint: Option<bool>,
+}
+
+/// Settings for a single tool of a server
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct ToolConfig {
+    pub output_limit: Option<OutputLimitConfig>,
+    /// Force caching on or off for this tool regardless of its annotations
+    pub cache: Option<bool>,
+    /// Park calls until a person approves them through the admin endpoint
+    #[serde(default)]
+    pub require_approval: bool,
+    pub retry: Option<RetryConfig>,
+    /// Limits calls to this tool over time, unlimited when not set
+    pub rate_limit: Option<RateLimit>,
+    pub description: Option<String>,
+    /// Lists the tool for a lazy server that was never started
+    pub input_schema: Option<Value>,
+    pub annotations: Option<ToolAnnotations>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ProxyMcpServer {
     pub default_args: Option<Value>,
+    pub output_limit: Option<OutputLimitConfig>,
+    /// Cache results of tools without `readOnlyHint` or `idempotentHint`
+    pub cache: Option<bool>,
+    /// Overrides the global `read_only` setting for this server
+    pub read_only: Option<bool>,
+    pub retry: Option<RetryConfig>,
+    /// Overrides the global `circuit_breaker` for this server
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Limits concurrent calls to this server, unlimited when not set
+    pub concurrency: Option<ConcurrencyConfig>,
+    /// Limits calls to this server over time, unlimited when not set
+    pub rate_limit: Option<RateLimit>,
+    /// Run several worker processes of a stdio server
+    pub pool: Option<PoolConfig>,
+    /// Overrides the global `restart` policy for this server
+    pub restart: Option<RestartConfig>,
+    /// Ping the server periodically to detect dead connections
+    pub keepalive: Option<KeepaliveConfig>,
+    #[serde(default)]
+    pub start: StartMode,
+    /// Seconds without calls after which the server is stopped until the next call
+    pub idle_timeout: Option<u64>,
+    /// Overrides the global `instances` limits for this server
+    pub instances: Option<InstanceConfig>,
+    /// Environment variables callers may supply in `_meta.env_vars`, none when not set
+    pub env_policy: Option<EnvPolicy>,
+    /// Per-tool settings keyed by the upstream tool name
+    #[serde(default)]
+    pub tools: HashMap<String, ToolConfig>,
     #[serde(flatten)]
     pub server_type: ProxyMcpServerType,
 }
@@ -54,17 +657,133 @@ pub enum ProxyMcpServerType {
     SSE {
         url: String,
         headers: Option<HashMap<String, String>>,
+        auth: Option<UpstreamAuth>,
     },
     #[serde(rename = "ws")]
     WS {
         url: String,
         headers: Option<HashMap<String, String>>,
+        auth: Option<UpstreamAuth>,
+    },
+    /// Streamable HTTP, messages are POSTed to `url` and answered with JSON or an SSE stream
+    #[serde(rename = "http")]
+    Http {
+        url: String,
+        headers: Option<HashMap<String, String>>,
+        auth: Option<UpstreamAuth>,
+    },
+    /// Replicas of one server behind a single logical name
+    #[serde(rename = "group")]
+    Group {
+        endpoints: Vec<ProxyMcpServerType>,
+        #[serde(default)]
+        strategy: BalanceStrategy,
+        #[serde(default)]
+        health: GroupHealthConfig,
+    },
+}
+
+/// Credentials sent in the `Authorization` header to an upstream server
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpstreamAuth {
+    Bearer {
+        token: String,
+    },
+    /// Bearer tokens from an OAuth2 client credentials grant
+    #[serde(rename = "oauth2")]
+    OAuth2 {
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        scope: Option<String>,
+        audience: Option<String>,
     },
+    /// Bearer tokens printed by a command, either the bare token or a JSON
+    /// object with `access_token` and optionally `expires_in`
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        /// Seconds a bare token is used for, until reconnecting when not set
+        refresh_secs: Option<u64>,
+    },
+}
+
+/// How a group picks the endpoint for a call
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    #[default]
+    RoundRobin,
+    LeastInFlight,
+    /// Always use the first healthy endpoint in configuration order
+    Priority,
+}
+
+/// When a group stops sending calls to a failing endpoint
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct GroupHealthConfig {
+    /// Consecutive failures before an endpoint is ejected
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Seconds an ejected endpoint is skipped
+    #[serde(default = "default_ejection_secs")]
+    pub ejection_secs: u64,
+}
+
+impl Default for GroupHealthConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            ejection_secs: default_ejection_secs(),
+        }
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+fn default_ejection_secs() -> u64 {
+    30
+}
+
+/// Rules granting clients access to servers, tools and resources.
+///
+/// A client may use what any rule matching it grants and nothing else.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct AuthorizationConfig {
+    #[serde(default)]
+    pub rules: Vec<AccessRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AccessRule {
+    /// Subject globs, matching the API key name or JWT `sub`
+    #[serde(default)]
+    pub subjects: Vec<String>,
+    /// Groups from the JWT `groups` claim, the rule matches every client
+    /// when neither subjects nor groups are set
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Server name globs, e.g. `"*"`
+    pub servers: Vec<String>,
+    /// Tool name globs on those servers, all tools when empty
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// Resource URI prefixes on those servers, all resources when empty
+    #[serde(default)]
+    pub resources: Vec<String>,
 }
 
+/// How clients authenticate to the proxy
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "auth_type", content = "value")]
 pub enum ProxyTransportAuth {
+    /// A shared secret sent as the bearer token
     Bearer(String),
+    /// Secret of the HS256 JWTs sent as bearer tokens
     JwtSecret(String),
+    /// Path of a keys file with named API keys, managed with `mcp-proxy keys`
+    KeysFile(String),
 }